@@ -0,0 +1,292 @@
+//! Format-preserving edits of the configuration file.
+//!
+//! [`Config`](crate::Config) keeps the parsed `toml_edit` document alongside
+//! its profiles, and mirrors every change made through its editing methods
+//! into the document, so that comments, whitespace and key order of the
+//! user's file survive.
+
+use crate::{Color, Profile, Value, split_key};
+use toml_edit::{Array, Decor, DocumentMut, Item, RawString, Table, TableLike};
+
+/// Convert a Git value into a TOML value.
+pub(crate) fn to_toml(val: &Value) -> toml_edit::Value {
+    match val {
+        Value::Boolean(b) => (*b).into(),
+        Value::ColorArray(v) => {
+            let mut array = Array::new();
+            for c in v.iter() {
+                array.push(match c {
+                    Color::Number(i) if *i >= 256 => format!("0x{:x}", i)
+                        .parse::<toml_edit::Value>()
+                        .unwrap_or_else(|_| (*i as i64).into()),
+                    Color::Number(i) => (*i as i64).into(),
+                    Color::String(s) => s.as_str().into(),
+                });
+            }
+            array.into()
+        }
+        Value::Integer(i) => (*i).into(),
+        Value::Multiple(v) => v.iter().map(to_toml).collect::<Array>().into(),
+        Value::String(s) => s.as_str().into(),
+    }
+}
+
+/// Convert a list of strings into a TOML value.
+fn to_toml_list(l: &[String]) -> toml_edit::Value {
+    l.iter().map(String::as_str).collect::<Array>().into()
+}
+
+/// Get the table of a profile, creating it if needed.
+fn profile_table<'a>(doc: &'a mut DocumentMut, name: &str) -> &'a mut dyn TableLike {
+    if !doc.get(name).is_some_and(Item::is_table_like) {
+        doc.insert(name, Item::Table(Table::new()));
+    }
+    doc[name]
+        .as_table_like_mut()
+        .expect("profile is a table in a parsed configuration")
+}
+
+/// Set an item at a dotted path beneath `table`, creating implicit dotted
+/// tables for missing intermediate segments.
+fn set_path(table: &mut dyn TableLike, path: &[&str], item: Item) {
+    match path {
+        [] => {}
+        [key] => {
+            match table.get_mut(key) {
+                // Keep the existing key formatting and decoration
+                Some(existing) if existing.is_value() && item.is_value() => {
+                    let decor = existing.as_value().map(|v| v.decor().clone());
+                    *existing = item;
+                    if let (Some(d), Some(v)) = (decor, existing.as_value_mut()) {
+                        *v.decor_mut() = d;
+                    }
+                }
+                _ => {
+                    table.insert(key, item);
+                }
+            }
+        }
+        [key, rest @ ..] => {
+            let is_table = table.get(key).is_some_and(Item::is_table_like);
+            if !is_table {
+                let mut t = Table::new();
+                t.set_dotted(true);
+                table.insert(key, Item::Table(t));
+            }
+            if let Some(t) = table.get_mut(key).and_then(Item::as_table_like_mut) {
+                set_path(t, rest, item);
+            }
+        }
+    }
+}
+
+/// Remove the item at a dotted path beneath `table`, along with any
+/// intermediate tables left empty. Returns whether an item was removed.
+fn remove_path(table: &mut dyn TableLike, path: &[&str]) -> bool {
+    match path {
+        [] => false,
+        [key] => table.remove(key).is_some(),
+        [key, rest @ ..] => {
+            let Some(t) = table.get_mut(key).and_then(Item::as_table_like_mut) else {
+                return false;
+            };
+            let removed = remove_path(t, rest);
+            if removed && t.is_empty() {
+                table.remove(key);
+            }
+            removed
+        }
+    }
+}
+
+/// Set or remove a top level setting in the document. Comments before a
+/// removed setting move to the next item, as they may describe the file.
+pub(crate) fn set_root(doc: &mut DocumentMut, key: &str, val: Option<toml_edit::Value>) {
+    match val {
+        Some(v) => set_path(doc.as_table_mut(), &[key], toml_edit::value(v)),
+        None => {
+            let prefix = doc
+                .as_table()
+                .key(key)
+                .and_then(|k| k.leaf_decor().prefix())
+                .and_then(RawString::as_str)
+                .map(String::from);
+            if doc.remove(key).is_some()
+                && let Some(prefix) = prefix
+            {
+                prepend_to_next(doc, &prefix);
+            }
+        }
+    }
+}
+
+/// Prepend text to the decoration of the first item of the document, which
+/// is its first top level value, else its first table.
+fn prepend_to_next(doc: &mut DocumentMut, text: &str) {
+    let prepend = |decor: &mut Decor| {
+        let prefix = decor.prefix().and_then(RawString::as_str).unwrap_or("");
+        decor.set_prefix(format!("{}{}", text, prefix));
+    };
+    let root = doc.as_table_mut();
+    // Top level values are rendered before tables
+    if let Some((mut key, _)) = root.iter_mut().find(|(_, item)| item.is_value()) {
+        prepend(key.leaf_decor_mut());
+        return;
+    }
+    let first = root
+        .iter_mut()
+        .filter_map(|(_, item)| item.as_table_mut())
+        .filter(|t| !t.is_implicit())
+        .min_by_key(|t| t.position());
+    match first {
+        Some(table) => prepend(table.decor_mut()),
+        None => {
+            let trailing = doc.trailing().as_str().unwrap_or("").to_string();
+            doc.set_trailing(format!("{}{}", text, trailing));
+        }
+    }
+}
+
+/// Set a Git configuration field of a profile in the document.
+pub(crate) fn set_field(doc: &mut DocumentMut, profile: &str, key: &str, val: &Value) {
+    let table = profile_table(doc, profile);
+    set_path(table, &split_key(key), toml_edit::value(to_toml(val)));
+}
+
+/// Remove a Git configuration field of a profile from the document.
+pub(crate) fn remove_field(doc: &mut DocumentMut, profile: &str, key: &str) {
+    if let Some(table) = doc.get_mut(profile).and_then(Item::as_table_like_mut) {
+        remove_path(table, &split_key(key));
+    }
+}
+
+/// Set or remove a gid setting of a profile in the document.
+fn set_setting(doc: &mut DocumentMut, profile: &str, key: &str, val: Option<toml_edit::Value>) {
+    let table = profile_table(doc, profile);
+    match val {
+        Some(v) => set_path(table, &[key], toml_edit::value(v)),
+        None => {
+            table.remove(key);
+        }
+    }
+}
+
+/// Replace a parent name in the `extends` setting of a profile, keeping the
+/// setting as a string or an array as written.
+pub(crate) fn rename_parent(doc: &mut DocumentMut, profile: &str, old: &str, new: &str) {
+    let Some(extends) = doc
+        .get_mut(profile)
+        .and_then(Item::as_table_like_mut)
+        .and_then(|t| t.get_mut("extends"))
+        .and_then(Item::as_value_mut)
+    else {
+        return;
+    };
+    let rename = |v: &mut toml_edit::Value| {
+        if v.as_str() == Some(old) {
+            let decor = v.decor().clone();
+            *v = new.into();
+            *v.decor_mut() = decor;
+        }
+    };
+    match extends.as_array_mut() {
+        Some(array) => array.iter_mut().for_each(rename),
+        None => rename(extends),
+    }
+}
+
+/// Mirror the differences between two versions of a profile into the
+/// document, leaving unchanged entries untouched.
+pub(crate) fn sync_profile(doc: &mut DocumentMut, old: Option<&Profile>, new: &Profile) {
+    let name = new.name.as_str();
+    profile_table(doc, name);
+
+    let default = Profile::new(name);
+    let old = old.unwrap_or(&default);
+
+    if old.is_abstract != new.is_abstract {
+        set_setting(doc, name, "abstract", new.is_abstract.then(|| true.into()));
+    }
+    let lists = [
+        ("extends", &old.extends, &new.extends),
+        ("directories", &old.directories, &new.directories),
+        ("remotes", &old.remotes, &new.remotes),
+    ];
+    for (key, old_list, new_list) in lists {
+        if old_list != new_list {
+            let val = (!new_list.is_empty()).then(|| to_toml_list(new_list));
+            set_setting(doc, name, key, val);
+        }
+    }
+
+    for key in old.fields.keys() {
+        if !new.fields.contains_key(key) {
+            remove_field(doc, name, key);
+        }
+    }
+    for (key, val) in new.fields.iter() {
+        if old.fields.get(key) != Some(val) {
+            set_field(doc, name, key, val);
+        }
+    }
+
+    if old.env != new.env {
+        let table = profile_table(doc, name);
+        if new.env.is_empty() {
+            table.remove("env");
+        } else {
+            if !table.get("env").is_some_and(Item::is_table_like) {
+                table.insert("env", Item::Table(Table::new()));
+            }
+            let env = table
+                .get_mut("env")
+                .and_then(Item::as_table_like_mut)
+                .expect("env table was just created");
+            for var in old.env.keys() {
+                if !new.env.contains_key(var) {
+                    env.remove(var);
+                }
+            }
+            for (var, val) in new.env.iter() {
+                if old.env.get(var) != Some(val) {
+                    let v: toml_edit::Value = match val {
+                        Some(s) => s.as_str().into(),
+                        None => false.into(),
+                    };
+                    set_path(env, &[var], toml_edit::value(v));
+                }
+            }
+        }
+    }
+}
+
+/// Render the section of a single profile as a document of its own.
+pub(crate) fn profile_source(doc: &DocumentMut, name: &str) -> Option<String> {
+    let item = doc.get(name).filter(|i| i.is_table_like())?;
+    let mut single = DocumentMut::new();
+    single.insert(name, item.clone());
+    Some(single.to_string())
+}
+
+/// Replace the section of a profile with the given item, placing it where
+/// the old section was.
+pub(crate) fn replace_profile(doc: &mut DocumentMut, name: &str, mut item: Item) {
+    if let Some(position) = doc
+        .get(name)
+        .and_then(Item::as_table)
+        .and_then(Table::position)
+    {
+        set_position(&mut item, position);
+    }
+    doc.insert(name, item);
+}
+
+/// Set the document position of a table and all tables nested in it.
+fn set_position(item: &mut Item, position: usize) {
+    if let Some(table) = item.as_table_mut() {
+        table.set_position(position);
+        for (_, child) in table.iter_mut() {
+            set_position(child, position);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::{fmt, io, path::PathBuf};
+
+/// Errors of the gid library and utilities.
+#[derive(Debug)]
+pub enum Error {
+    /// No configuration file was found in any of the searched locations.
+    NoConfig,
+    /// A file could not be read or written.
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// Configuration is not valid TOML. Line and column are 1-based.
+    Syntax {
+        message: String,
+        line: Option<usize>,
+        column: Option<usize>,
+    },
+    /// A configuration value has a type that cannot be represented as a Git
+    /// or gid setting.
+    UnsupportedValue { key: String, message: String },
+    /// Profile inheritance refers to missing profiles or contains a cycle.
+    Inheritance(String),
+    /// No profile was given and there is no active profile.
+    NoActiveProfile,
+    /// Named profile does not exist.
+    UnknownProfile(String),
+    /// Named profile is abstract and cannot be selected.
+    AbstractProfile(String),
+    /// Profile has no value for the given key.
+    UnknownField { profile: String, key: String },
+    /// A profile with the given name already exists.
+    ProfileExists(String),
+    /// Name cannot be used for a profile.
+    InvalidProfileName(String),
+    /// Profile has no rules to derive `includeIf` conditions from.
+    NoLinkConditions(String),
+    /// Exporting would overwrite the given number of differing Git
+    /// configuration values.
+    Conflicts(usize),
+    /// Git could not be run, or exited unsuccessfully.
+    Git { command: String, message: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoConfig => write!(
+                f,
+                "no configuration detected; learn how to initialize an empty \
+                 configuration file with `gidc help init`"
+            ),
+            Self::Io {
+                path: Some(p),
+                source,
+            } => write!(f, "{}: {}", p.display(), source),
+            Self::Io { path: None, source } => write!(f, "{}", source),
+            Self::Syntax {
+                message,
+                line: Some(l),
+                column: Some(c),
+            } => write!(
+                f,
+                "invalid configuration at line {}, column {}: {}",
+                l, c, message
+            ),
+            Self::Syntax { message, .. } => write!(f, "invalid configuration: {}", message),
+            Self::UnsupportedValue { key, message } => {
+                write!(f, "unsupported value for `{}`: {}", key, message)
+            }
+            Self::Inheritance(message) => write!(f, "{}", message),
+            Self::NoActiveProfile => write!(f, "no profile provided and no active profile"),
+            Self::UnknownProfile(name) => write!(f, "profile '{}' not found", name),
+            Self::AbstractProfile(name) => {
+                write!(f, "profile '{}' is abstract and cannot be selected", name)
+            }
+            Self::UnknownField { profile, key } => {
+                write!(f, "profile '{}' has no value for `{}`", profile, key)
+            }
+            Self::ProfileExists(name) => write!(f, "profile '{}' already exists", name),
+            Self::InvalidProfileName(name) => {
+                write!(f, "'{}' cannot be used as a profile name", name)
+            }
+            Self::NoLinkConditions(name) => write!(
+                f,
+                "profile '{}' has no directory or remote rules to link with; \
+                 use --gitdir or --remote",
+                name
+            ),
+            Self::Conflicts(count) => write!(
+                f,
+                "{} existing Git configuration value(s) differ from the profile; \
+                 use --force to overwrite",
+                count
+            ),
+            Self::Git { command, message } => {
+                write!(f, "`git {}` failed: {}", command, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Self {
+        Error::Io { path: None, source }
+    }
+}
+
+impl Error {
+    /// Create a syntax error from a TOML parse error, locating it in the
+    /// source text.
+    pub(crate) fn from_toml(e: toml::de::Error, source: &str) -> Self {
+        let (line, column) = match e.span() {
+            Some(span) => {
+                let before = &source[..span.start.min(source.len())];
+                let line = before.matches('\n').count() + 1;
+                let column = before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+                (Some(line), Some(column))
+            }
+            None => (None, None),
+        };
+        Error::Syntax {
+            message: e.message().trim().to_string(),
+            line,
+            column,
+        }
+    }
+}
@@ -1,33 +1,111 @@
-use gid::{Config, ToGitString};
-use std::{env, process::Command};
+use gid::{
+    Config, Error, Injection, Rule, git,
+    process::{EXIT_FAILURE, config_args, config_env, exec, exec_error_code, set_env},
+    repo::Repository,
+};
+use std::{env, ffi::OsString, fmt::Display, path::Path, process};
 
-fn main() {
-    let config_error_message = "No configuration detected. Learn how to \
-                                initialize an empty configuration file with
-                                `gidc help init`.";
+/// Environment variable counting nested runs of `gid` as a `git` shim.
+const SHIM_DEPTH_VAR: &str = "GID_SHIM_DEPTH";
 
-    let config = Config::detect().expect(config_error_message);
-    let config = Config::parse_file(&config).unwrap();
+/// Deepest nesting of shim runs before assuming the shim runs itself. Git
+/// legitimately nests through hooks and commands like `rebase --exec`, but
+/// rarely more than a few levels.
+const MAX_SHIM_DEPTH: usize = 16;
 
-    let profile = config.active.expect("no active profile");
-    let profile = config
-        .profiles
-        .get(&profile)
-        .expect(format!("profile '{}' not found", profile).as_str());
+/// Print an error message and exit with the given code.
+fn fail(message: impl Display, code: i32) -> ! {
+    eprintln!("gid: {}", message);
+    process::exit(code);
+}
 
-    let mut user_args: Vec<String> = env::args().collect();
-    user_args.remove(0);
+fn main() {
+    let mut user_args: Vec<OsString> = env::args_os().collect();
+    let program = user_args.remove(0);
 
-    let mut config_args: Vec<String> = Vec::new();
+    // Installed as `git`, arguments are all forwarded to Git
+    let is_shim = Path::new(&program)
+        .file_stem()
+        .is_some_and(|stem| stem == "git");
+    let shim_depth = if is_shim {
+        env::var(SHIM_DEPTH_VAR)
+            .ok()
+            .and_then(|d| d.parse::<usize>().ok())
+            .unwrap_or(0)
+    } else {
+        0
+    };
+    if shim_depth >= MAX_SHIM_DEPTH {
+        fail(
+            format!(
+                "git shim runs itself; set {} to the path of the Git executable",
+                git::PROGRAM_VAR
+            ),
+            EXIT_FAILURE,
+        );
+    }
 
-    for (key, val) in profile.fields.iter() {
-        config_args.push("-c".to_string());
-        config_args.push(format!("{}={}", key, val.to_git_string()));
+    // A shim runs Git without a profile rather than fail outside of gid's
+    // configured repositories
+    let config = match Config::detect() {
+        Some(path) => Some(Config::parse_file(&path).unwrap_or_else(|e| fail(e, EXIT_FAILURE))),
+        None if is_shim => None,
+        None => fail(Error::NoConfig, EXIT_FAILURE),
+    };
+    git::locate(config.as_ref().and_then(|c| c.git.as_deref()));
+
+    // Leading gid options, removed before forwarding arguments to Git
+    let mut profile_name: Option<String> = None;
+    loop {
+        let arg = user_args
+            .first()
+            .and_then(|a| a.to_str())
+            .filter(|_| !is_shim);
+        match arg {
+            Some("--gid-profile") => {
+                let Some(name) = user_args.get(1) else {
+                    fail("--gid-profile requires a profile name", EXIT_FAILURE);
+                };
+                profile_name = Some(name.to_string_lossy().to_string());
+                user_args.drain(..2);
+            }
+            Some(a) if a.starts_with("--gid-profile=") => {
+                profile_name = Some(a["--gid-profile=".len()..].to_string());
+                user_args.remove(0);
+            }
+            _ => break,
+        }
     }
 
-    Command::new("git")
-        .args(config_args)
-        .args(user_args)
-        .status()
-        .expect("failed to execute Git command");
+    let mut command = git::command();
+    if is_shim {
+        command.env(SHIM_DEPTH_VAR, (shim_depth + 1).to_string());
+    }
+    if let Some(config) = config {
+        let selection = match profile_name {
+            Some(ref name) => config.select_named(name, Rule::Argument),
+            None => config.select(Repository::from_git_args(&user_args).as_ref()),
+        };
+        match selection {
+            Ok(selection) => {
+                let profile = config
+                    .resolve_profile(&selection.profile.name)
+                    .unwrap_or_else(|e| fail(e, EXIT_FAILURE));
+                let pairs = profile.config_pairs();
+                set_env(&mut command, &profile.env);
+                match config.injection {
+                    Injection::Arguments => command.args(config_args(&pairs)),
+                    Injection::Environment => command.envs(config_env(&pairs)),
+                };
+            }
+            Err(Error::NoActiveProfile) if is_shim => {}
+            Err(e) => fail(e, EXIT_FAILURE),
+        }
+    }
+    command.args(user_args);
+    let e = exec(command);
+    fail(
+        format!("failed to execute Git command: {}", e),
+        exec_error_code(&e),
+    );
 }
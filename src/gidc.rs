@@ -1,14 +1,21 @@
-use clap::{Parser, Subcommand};
-use gid::{Config, FromGitStr, Profile, ToGitString, Value};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use gid::{
+    Color, Config, Error, FromGitStr, Profile, Rule, ToGitString, Value, find_key,
+    git::{self, Scope},
+    glob, is_color_key, is_env_name,
+    link::{self, Condition},
+    process::{config_env, config_env_changes, exec, exec_error_code, set_env},
+    repo::{self, Repository},
+    snapshot::{self, Snapshot},
+    split_key,
+};
 use std::{
     collections::{BTreeMap, BTreeSet},
-    fs,
-    io::{stdin, stdout, Write},
-    path::PathBuf,
-    process::Command,
-    str,
+    env, fs,
+    io::{Write, stdin, stdout},
+    path::{Path, PathBuf},
+    process, str,
 };
-use toml_edit::{value, DocumentMut};
 
 #[derive(Parser, Debug)]
 #[command(name = "gidc")]
@@ -18,33 +25,203 @@ use toml_edit::{value, DocumentMut};
 struct Cli {
     #[command(subcommand)]
     command: Action,
+
+    /// Answer yes to all questions.
+    #[arg(short, long, global = true, conflicts_with = "no")]
+    yes: bool,
+
+    /// Answer no to all questions.
+    #[arg(long, global = true)]
+    no: bool,
 }
 
 #[derive(Subcommand, Debug)]
 enum Action {
+    /// Copy a profile under a new name
+    Copy {
+        /// Overwrite the destination profile without asking.
+        #[arg(short, long)]
+        force: bool,
+
+        /// Profile to copy
+        source: String,
+
+        /// New profile name
+        destination: String,
+    },
+
+    /// Show differences between two profiles, or between a profile and Git
+    /// configuration
+    Diff {
+        #[command(flatten)]
+        scope: ScopeArgs,
+
+        /// Print tab separated lines of status, key, old and new values.
+        #[arg(long)]
+        porcelain: bool,
+
+        #[command(flatten)]
+        filter: KeyFilter,
+
+        /// Profile name. `GID_PROFILE` or active profile if not provided.
+        name: Option<String>,
+
+        /// Profile to compare against, instead of Git configuration.
+        #[arg(conflicts_with = "scope")]
+        other: Option<String>,
+    },
+
     /// Display profile settings
     Display {
-        /// Profile name. Active profile if not provided.
+        /// Show effective settings including inherited values, annotated with
+        /// the profile each value comes from.
+        #[arg(short, long)]
+        resolved: bool,
+
+        /// Profile name. `GID_PROFILE` or active profile if not provided.
+        name: Option<String>,
+    },
+
+    /// Edit the configuration file, or a single profile, in an editor
+    Edit {
+        /// Profile name. The whole configuration file if not provided.
+        name: Option<String>,
+    },
+
+    /// Print shell commands applying a profile to Git through environment
+    /// variables, as in `eval "$(gidc env)"`
+    Env {
+        /// Syntax of the printed commands.
+        #[arg(short, long, value_enum, default_value_t = Shell::Bash)]
+        shell: Shell,
+
+        /// Print commands reverting the variables set for the profile instead.
+        #[arg(short, long)]
+        unset: bool,
+
+        /// Profile name. `GID_PROFILE` or active profile if not provided.
         name: Option<String>,
     },
 
+    /// Run a command with a profile applied to every Git command it runs
+    Exec {
+        /// Profile to apply. Selected as `gid` would in the current directory
+        /// if not provided.
+        #[arg(short, long)]
+        profile: Option<String>,
+
+        /// Command to run, followed by its arguments.
+        #[arg(
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            value_name = "COMMAND"
+        )]
+        command: Vec<String>,
+    },
+
     /// Export profile settings to Git configuration
     Export {
-        /// Export to global Git configuration. Local if not set.
+        #[command(flatten)]
+        scope: ScopeArgs,
+
+        /// Print the changes without making them.
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// Overwrite existing values that differ from the profile.
         #[arg(short, long)]
-        global: bool,
+        force: bool,
+
+        /// Restore the values replaced by the most recent export.
+        #[arg(short, long, conflicts_with_all = ["name", "dry_run", "force"])]
+        undo: bool,
 
-        /// Profile name. Active profile if not provided.
+        #[command(flatten)]
+        filter: KeyFilter,
+
+        /// Pick the keys from a numbered list.
+        #[arg(short, long)]
+        interactive: bool,
+
+        /// Profile name. `GID_PROFILE` or active profile if not provided.
         name: Option<String>,
     },
 
+    /// Remove profile settings from Git configuration
+    Unexport {
+        #[command(flatten)]
+        scope: ScopeArgs,
+
+        /// Print the changes without making them.
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// Only remove keys whose values still match the profile.
+        #[arg(long)]
+        if_unchanged: bool,
+
+        /// Profile name. `GID_PROFILE` or active profile if not provided.
+        name: Option<String>,
+    },
+
+    /// Edit a Git configuration field of a profile
+    Field {
+        #[command(subcommand)]
+        action: FieldAction,
+    },
+
+    /// Print the value of a Git configuration field of a profile
+    Get {
+        /// Include values inherited with `extends`.
+        #[arg(short, long)]
+        resolved: bool,
+
+        /// Profile name. `GID_PROFILE` or active profile if not provided.
+        #[arg(short, long)]
+        profile: Option<String>,
+
+        /// Git configuration key, such as `user.name`
+        key: String,
+    },
+
     /// Import Git configuration to profile
     Import {
-        /// Import global Git configuration. Local if not set.
+        #[command(flatten)]
+        scope: ScopeArgs,
+
+        /// Keep the values of an existing profile, only adding new keys.
+        #[arg(long, conflicts_with_all = ["update", "resolve"])]
+        merge: bool,
+
+        /// Update an existing profile with the imported values, keeping its
+        /// other keys.
+        #[arg(long, conflicts_with = "resolve")]
+        update: bool,
+
+        /// Ask which value to keep for each key of an existing profile that
+        /// differs from the imported value.
+        #[arg(long)]
+        resolve: bool,
+
+        #[command(flatten)]
+        filter: KeyFilter,
+
+        /// Pick the keys from a numbered list.
         #[arg(short, long)]
-        global: bool,
+        interactive: bool,
 
-        /// New or existing profile name. Active profile if not provided.
+        /// Import each file included from global Git configuration as a
+        /// profile, with rules from the conditions of its `includeIf`
+        /// directives.
+        #[arg(
+            long,
+            conflicts_with_all = ["scope", "merge", "update", "resolve", "interactive", "name"]
+        )]
+        discover: bool,
+
+        /// New or existing profile name. `GID_PROFILE` or active profile if not
+        /// provided.
         name: Option<String>,
     },
 
@@ -63,176 +240,1443 @@ enum Action {
         path: Option<String>,
     },
 
+    /// Link a profile into global Git configuration with conditional
+    /// includes, so that Git uses it without `gid`
+    Link {
+        /// Include for repositories matching a directory pattern, written as
+        /// in `directories` rules. May be repeated.
+        #[arg(long, value_name = "PATTERN")]
+        gitdir: Vec<String>,
+
+        /// Include for repositories with a remote URL matching a pattern,
+        /// written as in `remotes` rules. May be repeated.
+        #[arg(long, value_name = "PATTERN")]
+        remote: Vec<String>,
+
+        /// Profile name. Its directory and remote rules are used if no
+        /// patterns are provided.
+        name: String,
+    },
+
     /// List all profiles
     List,
 
+    /// Create an empty profile
+    New {
+        /// Overwrite an existing profile of the same name without asking.
+        #[arg(short, long)]
+        force: bool,
+
+        /// Profile name
+        name: String,
+    },
+
+    /// Delete a profile
+    Remove {
+        /// Delete without asking.
+        #[arg(short, long)]
+        force: bool,
+
+        /// Profile name
+        name: String,
+    },
+
+    /// Rename a profile
+    Rename {
+        /// Overwrite the destination profile without asking.
+        #[arg(short, long)]
+        force: bool,
+
+        /// Current profile name
+        old: String,
+
+        /// New profile name
+        new: String,
+    },
+
     /// Set active profile
     Set {
         /// Profile name
         name: String,
     },
+
+    /// Regenerate the files of linked profiles
+    Sync,
+
+    /// Remove conditional includes of a linked profile
+    Unlink {
+        /// Only remove the include for a directory pattern. May be
+        /// repeated.
+        #[arg(long, value_name = "PATTERN")]
+        gitdir: Vec<String>,
+
+        /// Only remove the include for a remote URL pattern. May be repeated.
+        #[arg(long, value_name = "PATTERN")]
+        remote: Vec<String>,
+
+        /// Profile name
+        name: String,
+    },
+
+    /// Manage `git` shims running `gid` for every Git command
+    Shim {
+        #[command(subcommand)]
+        action: ShimAction,
+    },
+
+    /// Show which profile `gid` selects in a repository, and why
+    Which {
+        /// Directory to check. Current directory if not provided.
+        path: Option<String>,
+    },
+}
+
+/// Git configuration scope, as selected in `git config`. Local if not set.
+#[derive(Args, Debug)]
+#[group(id = "scope", multiple = false)]
+struct ScopeArgs {
+    /// Use the local Git configuration of the repository.
+    #[arg(short, long)]
+    local: bool,
+
+    /// Use the global Git configuration of the user.
+    #[arg(short, long)]
+    global: bool,
+
+    /// Use the system Git configuration.
+    #[arg(long)]
+    system: bool,
+
+    /// Use the Git configuration of the current worktree.
+    #[arg(long)]
+    worktree: bool,
+
+    /// Use a standalone Git configuration file.
+    #[arg(long, value_name = "PATH")]
+    file: Option<PathBuf>,
+}
+
+impl ScopeArgs {
+    /// Selected scope.
+    fn scope(&self) -> Scope {
+        if let Some(path) = &self.file {
+            Scope::File(path.to_path_buf())
+        } else if self.global {
+            Scope::Global
+        } else if self.system {
+            Scope::System
+        } else if self.worktree {
+            Scope::Worktree
+        } else {
+            Scope::Local
+        }
+    }
+}
+
+/// Selection of the Git configuration keys to import, export or compare.
+#[derive(Args, Debug)]
+struct KeyFilter {
+    /// Only include keys matching a glob pattern, such as `user.*`. May be
+    /// repeated.
+    #[arg(long, value_name = "GLOB")]
+    only: Vec<String>,
+
+    /// Leave out keys matching a glob pattern. May be repeated.
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+}
+
+/// Keys left out of imports and comparisons with Git configuration unless
+/// selected with `--only`, as they describe the repository rather than an
+/// identity.
+const IMPORT_DENYLIST: &[&str] = &[
+    "core.repositoryformatversion",
+    "core.filemode",
+    "core.bare",
+    "core.logallrefupdates",
+    "core.ignorecase",
+    "core.precomposeunicode",
+    "core.symlinks",
+    "core.worktree",
+    "extensions.*",
+    "remote.*",
+    "branch.*",
+    "submodule.*",
+];
+
+impl KeyFilter {
+    /// Check whether a key is selected. Keys matching `denylist` are only
+    /// selected if they match an `--only` pattern. Matching ignores case.
+    fn matches(&self, key: &str, denylist: &[&str]) -> bool {
+        let key = key.to_lowercase();
+        let matches = |pattern: &str| glob::matches(&pattern.to_lowercase(), &key);
+        let only = self.only.iter().any(|p| matches(p));
+        if !self.only.is_empty() && !only {
+            return false;
+        }
+        if self.exclude.iter().any(|p| matches(p)) {
+            return false;
+        }
+        only || !denylist.iter().any(|p| matches(p))
+    }
+}
+
+/// Let the user pick among keys and their values if `interactive`, and
+/// return the picked keys.
+fn pick<'a>(
+    entries: &[(&'a str, String)],
+    action: &str,
+    interactive: bool,
+) -> Result<Vec<&'a str>, Error> {
+    if !interactive || entries.is_empty() {
+        return Ok(entries.iter().map(|(key, _)| *key).collect());
+    }
+    for (i, (key, value)) in entries.iter().enumerate() {
+        println!("{:>4}. {} = {}", i + 1, key, value.replace('\n', "\\n"));
+    }
+    loop {
+        print!(
+            "Keys to {} (numbers or ranges such as `1 3-5`, `all` or `none`): ",
+            action
+        );
+        let _ = stdout().flush();
+        let mut answer = String::new();
+        if stdin().read_line(&mut answer)? == 0 {
+            println!();
+            return Ok(Vec::new());
+        }
+        match parse_picks(answer.trim(), entries.len()) {
+            Some(picked) => return Ok(picked.into_iter().map(|i| entries[i].0).collect()),
+            None => println!("Invalid selection: {}", answer.trim()),
+        }
+    }
+}
+
+/// Parse a selection of 1-based list numbers and ranges into sorted 0-based
+/// indices, or `None` if it is invalid.
+fn parse_picks(answer: &str, len: usize) -> Option<BTreeSet<usize>> {
+    match answer.to_lowercase().as_str() {
+        "all" | "a" => return Some((0..len).collect()),
+        "none" | "n" | "" => return Some(BTreeSet::new()),
+        _ => {}
+    }
+    let mut picked = BTreeSet::new();
+    for word in answer.split(|c: char| c == ',' || c.is_whitespace()) {
+        if word.is_empty() {
+            continue;
+        }
+        let (start, end) = match word.split_once('-') {
+            Some((start, end)) => (start.parse::<usize>().ok()?, end.parse::<usize>().ok()?),
+            None => {
+                let i = word.parse::<usize>().ok()?;
+                (i, i)
+            }
+        };
+        if start == 0 || start > end || end > len {
+            return None;
+        }
+        picked.extend(start - 1..end);
+    }
+    Some(picked)
+}
+
+#[derive(Subcommand, Debug)]
+enum FieldAction {
+    /// Set a field, replacing any existing value
+    Set {
+        /// Profile name. `GID_PROFILE` or active profile if not provided.
+        #[arg(short, long)]
+        profile: Option<String>,
+
+        /// Type of the value. Detected from the value if not provided.
+        #[arg(short, long, value_name = "TYPE")]
+        r#type: Option<ValueType>,
+
+        /// Git configuration key, such as `user.name`
+        key: String,
+
+        /// Value in Git configuration syntax
+        value: String,
+    },
+
+    /// Remove a field
+    Unset {
+        /// Profile name. `GID_PROFILE` or active profile if not provided.
+        #[arg(short, long)]
+        profile: Option<String>,
+
+        /// Git configuration key, such as `user.name`
+        key: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ShimAction {
+    /// Install a `git` shim running `gid` in a directory, to be put on `PATH`
+    /// before the directory of Git
+    Install {
+        /// Directory to install the shim in.
+        dir: PathBuf,
+    },
+}
+
+/// Syntax of environment variable assignments printed by `gidc env`.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+    Dotenv,
+}
+
+impl Shell {
+    /// Command setting an environment variable, or removing it if `None`.
+    /// Dotenv files cannot remove variables, so they are set empty instead.
+    /// Names are written unquoted, so only portable names are accepted.
+    fn assign(self, name: &str, value: Option<&str>) -> Result<String, Error> {
+        if !is_env_name(name) {
+            return Err(Error::UnsupportedValue {
+                key: name.to_string(),
+                message: "not a valid environment variable name".to_string(),
+            });
+        }
+        let command = match (self, value) {
+            (Self::Bash | Self::Zsh, Some(v)) => {
+                format!("export {}='{}'", name, v.replace('\'', "'\\''"))
+            }
+            (Self::Bash | Self::Zsh, None) => format!("unset {}", name),
+            (Self::Fish, Some(v)) => format!(
+                "set -gx {} '{}'",
+                name,
+                v.replace('\\', "\\\\").replace('\'', "\\'")
+            ),
+            (Self::Fish, None) => format!("set -e {}", name),
+            (Self::Powershell, Some(v)) => {
+                // Typographic single quotes also delimit PowerShell strings
+                let quoted: String = v
+                    .chars()
+                    .flat_map(|c| match c {
+                        '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}' => vec![c, c],
+                        _ => vec![c],
+                    })
+                    .collect();
+                format!("$env:{} = '{}'", name, quoted)
+            }
+            (Self::Powershell, None) => {
+                format!("Remove-Item Env:{} -ErrorAction SilentlyContinue", name)
+            }
+            (Self::Dotenv, v) => {
+                let escaped = v
+                    .unwrap_or_default()
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('$', "\\$")
+                    .replace('\n', "\\n");
+                format!("{}=\"{}\"", name, escaped)
+            }
+        };
+        Ok(command)
+    }
+}
+
+/// Type of a value given on the command line.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ValueType {
+    Bool,
+    Int,
+    Color,
+    String,
+}
+
+impl ValueType {
+    /// Parse a value in Git configuration syntax as this type.
+    fn parse(self, key: &str, value: &str) -> Result<Value, Error> {
+        let invalid = |message: &str| Error::UnsupportedValue {
+            key: key.to_string(),
+            message: format!("{}: {}", message, value),
+        };
+        match self {
+            Self::Bool => match value.to_lowercase().as_str() {
+                "true" | "yes" | "on" | "1" => Ok(Value::Boolean(true)),
+                "false" | "no" | "off" | "0" | "" => Ok(Value::Boolean(false)),
+                _ => Err(invalid("invalid boolean")),
+            },
+            Self::Int => {
+                // Git accepts k, m and g suffixes on integers
+                let lower = value.to_lowercase();
+                let (digits, factor) = match lower.as_bytes().last() {
+                    Some(b'k') => (&lower[..lower.len() - 1], 1 << 10),
+                    Some(b'm') => (&lower[..lower.len() - 1], 1 << 20),
+                    Some(b'g') => (&lower[..lower.len() - 1], 1 << 30),
+                    _ => (lower.as_str(), 1),
+                };
+                digits
+                    .parse::<i64>()
+                    .ok()
+                    .and_then(|i| i.checked_mul(factor))
+                    .map(Value::Integer)
+                    .ok_or_else(|| invalid("invalid integer"))
+            }
+            Self::Color if !is_color_key(key) => {
+                Err(invalid("colors are only supported for `color.*` keys"))
+            }
+            Self::Color => value
+                .split_whitespace()
+                .map(Color::from_git_str)
+                .collect::<Result<Vec<_>, _>>()
+                .map(Value::ColorArray),
+            Self::String => Ok(Value::String(value.to_string())),
+        }
+    }
 }
 
 fn main() {
     let cli = Cli::parse();
-    let config_path = Config::detect();
-    let config_string: Option<String> = if let Some(ref cp) = config_path {
-        Some(fs::read_to_string(&cp).unwrap())
-    } else {
-        None
+    if let Err(e) = run(&cli) {
+        eprintln!("gidc: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Write a file atomically, by writing a temporary file next to it and
+/// renaming it into place, reporting errors with its path.
+fn write_file(path: &Path, contents: &str) -> Result<(), Error> {
+    let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let file_name = target
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let temp = target.with_file_name(format!(".{}.tmp", file_name));
+    let io_error = |source| Error::Io {
+        path: Some(path.to_path_buf()),
+        source,
     };
-    let config: Option<Config> = if let Some(ref s) = config_string {
-        Some(s.parse::<Config>().unwrap())
-    } else {
-        None
+
+    let mut file = fs::File::create(&temp).map_err(io_error)?;
+    // Keep the permissions of the file being replaced
+    if let Ok(metadata) = fs::metadata(&target) {
+        file.set_permissions(metadata.permissions())
+            .map_err(io_error)?;
+    }
+    file.write_all(contents.as_bytes()).map_err(io_error)?;
+    drop(file);
+    fs::rename(&temp, &target).map_err(|e| {
+        let _ = fs::remove_file(&temp);
+        io_error(e)
+    })
+}
+
+/// Prefix of the error annotations added to files opened in the editor.
+const ANNOTATION: &str = "# gidc: ";
+
+/// Open a file in the user's editor, from `VISUAL` or `EDITOR`. Returns
+/// whether the editor exited successfully.
+fn open_editor(path: &Path) -> Result<bool, Error> {
+    let editor = env::var("VISUAL")
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .or_else(|| env::var("EDITOR").ok().filter(|e| !e.trim().is_empty()))
+        .unwrap_or_else(|| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let status = process::Command::new(program)
+        .args(words)
+        .arg(path)
+        .status()
+        .map_err(|e| Error::Io {
+            path: Some(PathBuf::from(program)),
+            source: e,
+        })?;
+    Ok(status.success())
+}
+
+/// Remove the error annotations at the start of an edited file.
+fn strip_annotations(text: &str) -> String {
+    let mut lines = text.split_inclusive('\n').peekable();
+    while lines.next_if(|l| l.starts_with(ANNOTATION)).is_some() {}
+    lines.collect()
+}
+
+/// Add error annotations to the start of a file to be edited again. Line
+/// numbers of syntax errors are shifted to match the annotated file.
+fn annotate(text: &str, error: Error) -> String {
+    const HINT: &str = "Fix the error and save, or save an empty file to abort.";
+    let render = |e: &Error| {
+        let mut annotation = String::new();
+        for line in e.to_string().lines().chain([HINT]) {
+            annotation.push_str(ANNOTATION);
+            annotation.push_str(line);
+            annotation.push('\n');
+        }
+        annotation
+    };
+    let annotation = match error {
+        Error::Syntax {
+            message,
+            line: Some(l),
+            column,
+        } => {
+            let shift = render(&Error::Syntax {
+                message: message.to_string(),
+                line: Some(l),
+                column,
+            })
+            .lines()
+            .count();
+            render(&Error::Syntax {
+                message,
+                line: Some(l + shift),
+                column,
+            })
+        }
+        e => render(&e),
     };
-    let config_doc: Option<DocumentMut> = if let Some(ref s) = config_string {
-        Some(s.parse::<DocumentMut>().unwrap())
-    } else {
-        None
+    annotation + text
+}
+
+/// Edit text in the user's editor until `accept` succeeds with the result,
+/// returning `None` if the user aborts or makes no changes. Text that is
+/// already invalid opens with its error noted.
+fn edit_until_valid<T>(
+    original: &str,
+    accept: impl Fn(&str) -> Result<T, Error>,
+) -> Result<Option<T>, Error> {
+    // The file may hold secrets, so it is kept in a new directory only the
+    // user can access
+    let dir = env::temp_dir().join(format!("gid-edit-{}", process::id()));
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(&dir).map_err(|e| Error::Io {
+        path: Some(dir.to_path_buf()),
+        source: e,
+    })?;
+    let temp = dir.join("gid.toml");
+    let io_error = |source| Error::Io {
+        path: Some(temp.to_path_buf()),
+        source,
     };
+    let result = (|| {
+        let mut text = match accept(original) {
+            Ok(_) => original.to_string(),
+            Err(e) => annotate(original, e),
+        };
+        loop {
+            fs::write(&temp, &text).map_err(io_error)?;
+            if !open_editor(&temp)? {
+                println!("Editor exited unsuccessfully; configuration unchanged");
+                return Ok(None);
+            }
+            let edited = fs::read_to_string(&temp).map_err(io_error)?;
+            let edited = strip_annotations(&edited);
+            if edited.trim().is_empty() {
+                println!("Edit aborted; configuration unchanged");
+                return Ok(None);
+            }
+            if edited == original {
+                println!("No changes made");
+                return Ok(None);
+            }
+            match accept(&edited) {
+                Ok(value) => return Ok(Some(value)),
+                Err(e) => text = annotate(&edited, e),
+            }
+        }
+    })();
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
 
-    let config_error_message = "No configuration detected. Learn how to \
-                                initialize an empty configuration file with
-                                `gidc help init`.";
+/// Name of the profile given on the command line, or else the current
+/// profile of the configuration.
+fn profile_name(name: &Option<String>, config: &Config) -> Result<String, Error> {
+    name.clone()
+        .or_else(|| config.current())
+        .ok_or(Error::NoActiveProfile)
+}
 
-    match &cli.command {
-        Action::Display { name } => {
-            config_path.as_ref().expect(config_error_message);
-            let config = config.unwrap();
-
-            let mut profile = name;
-            if let None = name {
-                profile = &config.active;
-            }
-
-            let profile = profile
-                .as_ref()
-                .expect("no profile provided and no active profile");
-            let profile = config
-                .profiles
-                .get(profile)
-                .expect(format!("profile '{}' not found'", profile).as_str());
-
-            println!("{}", profile.to_string());
-        }
-        Action::Export { global, name } => {
-            config_path.as_ref().expect(config_error_message);
-            let config = config.unwrap();
-
-            let mut profile = name;
-            if let None = name {
-                profile = &config.active;
-            }
-
-            let profile = profile
-                .as_ref()
-                .expect("no profile provided and no active profile");
-            let profile = config
-                .profiles
-                .get(profile)
-                .expect(format!("profile '{}' not found'", profile).as_str());
-
-            let base_args = vec!["config", if *global { "--global" } else { "--local" }];
-
-            for (key, val) in profile.fields.iter() {
-                Command::new("git")
-                    .args(&base_args)
-                    .arg(key)
-                    .arg(val.to_git_string())
-                    .status()
-                    .expect("failed to execute Git command");
-            }
-        }
-        Action::Import { global, name } => {
-            config_path.as_ref().expect(config_error_message);
-            let ref config_path = config_path.unwrap();
-            let mut config = config.unwrap();
-
-            let mut profile = name;
-            if let None = name {
-                profile = &config.active;
-            }
-            let profile = profile
-                .as_ref()
-                .expect("no profile provided and no active profile");
-
-            if let Some(_) = config.profiles.get(profile) {
-                let mut user_confirmation = String::new();
-                while user_confirmation != "y"
-                    && user_confirmation != "n"
-                    && user_confirmation != "yes"
-                    && user_confirmation != "no"
+/// Check whether a profile may be written under `name`, asking before
+/// overwriting an existing profile unless forced.
+fn may_overwrite(
+    config: &Config,
+    name: &str,
+    force: bool,
+    answer: Option<bool>,
+) -> Result<bool, Error> {
+    if force || !config.profiles.contains(name) {
+        return Ok(true);
+    }
+    confirm(
+        &format!("Profile {} already exists; overwrite?", name),
+        answer,
+    )
+}
+
+/// Remove a profile, refusing if other profiles extend it.
+fn remove_profile(config: &mut Config, name: &str) -> Result<(), Error> {
+    let children = config.children(name);
+    if !children.is_empty() {
+        return Err(Error::Inheritance(format!(
+            "profile '{}' is extended by {}",
+            name,
+            children.join(", ")
+        )));
+    }
+    config.remove_profile(name);
+    Ok(())
+}
+
+/// Check that the configuration still parses to the same fields of a
+/// profile after editing `key`, which fails if the edit clashes with how
+/// other keys are stored.
+fn validate_fields(config: &Config, profile: &str, key: &str) -> Result<(), Error> {
+    let reparsed = config.to_string().parse::<Config>()?;
+    let fields = |c: &Config| c.profiles.get(profile).map(|p| p.fields.clone());
+    if fields(&reparsed) != fields(config) {
+        return Err(Error::UnsupportedValue {
+            key: key.to_string(),
+            message: "value cannot be stored alongside the other keys of the profile".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Values of Git configuration keys, in Git string form.
+type GitValues = BTreeMap<String, Vec<String>>;
+
+/// Values of the fields of a profile.
+fn profile_values(profile: &Profile) -> GitValues {
+    profile
+        .fields
+        .iter()
+        .map(|(key, val)| {
+            let values = val.values().iter().map(|v| v.to_git_string()).collect();
+            (git::canonical_key(key), values)
+        })
+        .collect()
+}
+
+/// Values of a Git configuration scope.
+fn scope_values(scope: &Scope) -> Result<GitValues, Error> {
+    let mut values = GitValues::new();
+    for entry in git::list(scope)? {
+        // Keys without a value are true
+        let value = entry.value.unwrap_or_else(|| true.to_string());
+        values
+            .entry(git::canonical_key(&entry.key))
+            .or_default()
+            .push(value);
+    }
+    Ok(values)
+}
+
+/// Escape a porcelain diff value.
+fn escape(values: &[String]) -> String {
+    values
+        .join("\n")
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+}
+
+/// Render the keys added, removed and changed from `old` to `new`.
+fn render_diff(old: &GitValues, new: &GitValues, porcelain: bool) -> String {
+    let mut diff = String::new();
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    for key in keys {
+        let (o, n) = (old.get(key), new.get(key));
+        if o == n {
+            continue;
+        }
+        if porcelain {
+            let status = match (o, n) {
+                (Some(_), None) => "removed",
+                (None, Some(_)) => "added",
+                _ => "changed",
+            };
+            let render = |v: Option<&Vec<String>>| v.map_or(String::new(), |v| escape(v));
+            diff += &format!("{}\t{}\t{}\t{}\n", status, key, render(o), render(n));
+        } else {
+            for v in o.into_iter().flatten() {
+                diff += &format!("- {} = {}\n", key, v);
+            }
+            for v in n.into_iter().flatten() {
+                diff += &format!("+ {} = {}\n", key, v);
+            }
+        }
+    }
+    diff
+}
+
+/// Read the fields of a profile from a Git configuration scope, leaving out
+/// keys not selected by the filter and reporting entries that cannot be
+/// represented.
+fn read_fields(scope: &Scope, filter: &KeyFilter) -> Result<BTreeMap<String, Value>, Error> {
+    let mut fields = BTreeMap::new();
+    for entry in git::list(scope)? {
+        let key = entry.key;
+        if !filter.matches(&key, IMPORT_DENYLIST) {
+            continue;
+        }
+        if !entry.is_utf8 {
+            eprintln!("gidc: skipping `{}`: not valid UTF-8", key);
+            continue;
+        }
+        let v = match entry.value {
+            // Keys without a value are true
+            None => Value::Boolean(true),
+            Some(value) if is_color_key(&key) => ValueType::Color
+                .parse(&key, &value)
+                .or_else(|_| Value::from_git_str(&value))?,
+            Some(value) => Value::from_git_str(&value)?,
+        };
+        let v = match fields.remove(&key) {
+            // Colors have a single value, the last one set
+            _ if is_color_key(&key) => v,
+            // Collect repeated keys into a multi-valued field
+            Some(Value::Multiple(mut values)) => {
+                values.push(v);
+                Value::Multiple(values)
+            }
+            Some(previous) => Value::Multiple(vec![previous, v]),
+            None => v,
+        };
+        fields.insert(key, v);
+    }
+
+    // Git allows both `a.b` and `a.b.c`, which TOML cannot hold
+    let keys: Vec<Vec<&str>> = fields.keys().map(|k| split_key(k)).collect();
+    let clashing: Vec<String> = fields
+        .keys()
+        .zip(keys.iter())
+        .filter(|(_, path)| {
+            keys.iter()
+                .any(|other| other.len() < path.len() && path.starts_with(other))
+        })
+        .map(|(key, _)| key.to_string())
+        .collect();
+    for key in clashing {
+        eprintln!(
+            "gidc: skipping `{}`: clashes with a shorter key of the same name",
+            key
+        );
+        fields.remove(&key);
+    }
+    Ok(fields)
+}
+
+/// Global Git configuration files, in the order Git reads them.
+fn global_files() -> Vec<PathBuf> {
+    if let Some(path) = env::var_os("GIT_CONFIG_GLOBAL") {
+        return vec![PathBuf::from(path)]
+            .into_iter()
+            .filter(|p| p.is_file())
+            .collect();
+    }
+    let home = repo::home_dir();
+    let xdg = env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|h| h.join(".config")));
+    [
+        xdg.map(|d| d.join("git").join("config")),
+        home.map(|h| h.join(".gitconfig")),
+    ]
+    .into_iter()
+    .flatten()
+    .filter(|p| p.is_file())
+    .collect()
+}
+
+/// Resolve the path of an include directive of the Git configuration file
+/// `file`, as Git does: `~/` is the home directory, and relative paths are
+/// relative to the directory of `file`.
+fn include_path(path: &str, file: &Path) -> PathBuf {
+    if let Some(rest) = path.strip_prefix("~/")
+        && let Some(home) = repo::home_dir()
+    {
+        return home.join(rest);
+    }
+    let path = file.parent().unwrap_or(Path::new("")).join(path);
+    std::path::absolute(&path).unwrap_or(path)
+}
+
+/// Condition of an `includeIf` directive of the Git configuration file
+/// `file`, if gid rules can express it.
+fn include_condition(subsection: &str, file: &Path) -> Option<Condition> {
+    // Rules are matched case-sensitively, which covers the common case
+    let subsection = match subsection.strip_prefix("gitdir/i:") {
+        Some(pattern) => format!("gitdir:{}", pattern),
+        None => subsection.to_string(),
+    };
+    let condition = Condition::parse(&subsection)?;
+    if let Condition::GitDir(pattern) = &condition
+        && let Some(rest) = pattern.strip_prefix("./")
+    {
+        let dir = include_path(".", file);
+        return Some(Condition::GitDir(format!("{}/{}", dir.display(), rest)));
+    }
+    Some(condition)
+}
+
+/// Name of a profile imported from an included file: the file name without
+/// a `.gitconfig-` prefix or an extension, so that `~/.gitconfig-work` and
+/// `~/work.gitconfig` are both `work`.
+fn include_name(path: &Path) -> String {
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = [".gitconfig-", ".gitconfig_", "gitconfig-", "gitconfig_"]
+        .iter()
+        .find_map(|prefix| file_name.strip_prefix(prefix))
+        .unwrap_or(&file_name);
+    let name = Path::new(name)
+        .file_stem()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    name.trim_start_matches('.').to_string()
+}
+
+/// Profiles of the files included by the global Git configuration, with
+/// rules from the conditions of the directives including them, along with
+/// the path of each file. Files in the profiles directory of gid are left
+/// out, as they are written from existing profiles.
+fn discover(config_dir: &Path, filter: &KeyFilter) -> Result<Vec<(PathBuf, Profile)>, Error> {
+    let own = link::profiles_dir(config_dir);
+    let mut discovered: Vec<(PathBuf, Profile)> = Vec::new();
+    let mut skipped = BTreeSet::new();
+    for file in global_files() {
+        for entry in git::list(&Scope::File(file.clone()))? {
+            let subsection = match split_key(&entry.key)[..] {
+                [section, name]
+                    if section.eq_ignore_ascii_case("include")
+                        && name.eq_ignore_ascii_case("path") =>
                 {
-                    println!(
-                        "Profile {} already exists; overwrite while importing?",
-                        &profile
-                    );
-                    print!("[Y]es, [N]o: ");
-                    let _ = stdout().flush();
-                    stdin()
-                        .read_line(&mut user_confirmation)
-                        .expect("failed to parse user input");
-                    user_confirmation = user_confirmation.trim().to_lowercase();
+                    None
+                }
+                [section, subsection, name]
+                    if section.eq_ignore_ascii_case("includeIf")
+                        && name.eq_ignore_ascii_case("path") =>
+                {
+                    Some(subsection)
                 }
+                _ => continue,
+            };
+            let Some(value) = entry.value else {
+                continue;
+            };
+            let path = include_path(&value, &file);
+            if path.parent() == Some(own.as_path()) || skipped.contains(&path) {
+                continue;
+            }
+            if !path.is_file() {
+                eprintln!("gidc: skipping `{}`: no such file", value);
+                skipped.insert(path);
+                continue;
+            }
 
-                if user_confirmation != "y" && user_confirmation != "yes" {
-                    return;
+            let index = match discovered.iter().position(|(p, _)| *p == path) {
+                Some(i) => i,
+                None => {
+                    let name = include_name(&path);
+                    if discovered.iter().any(|(_, p)| p.name == name) {
+                        eprintln!(
+                            "gidc: skipping `{}`: another file is imported as profile {}",
+                            value, name
+                        );
+                        skipped.insert(path);
+                        continue;
+                    }
+                    let mut profile = Profile::new(&name);
+                    profile.fields = read_fields(&Scope::File(path.clone()), filter)?;
+                    discovered.push((path, profile));
+                    discovered.len() - 1
+                }
+            };
+            if let Some(subsection) = subsection {
+                match include_condition(subsection, &file) {
+                    Some(condition) => condition.add_rule(&mut discovered[index].1),
+                    None => eprintln!(
+                        "gidc: ignoring condition `{}` of `{}`: not supported by profile rules",
+                        subsection, value
+                    ),
                 }
+            }
+        }
+    }
+    Ok(discovered)
+}
+
+/// Directory of the gid configuration file.
+fn config_dir(config_path: &Option<PathBuf>) -> Result<&Path, Error> {
+    config_path
+        .as_ref()
+        .and_then(|p| p.parent())
+        .ok_or(Error::NoConfig)
+}
+
+/// Conditions given on the command line, as written.
+fn conditions(gitdir: &[String], remote: &[String]) -> Vec<Condition> {
+    gitdir
+        .iter()
+        .map(|p| Condition::GitDir(p.to_string()))
+        .chain(remote.iter().map(|p| Condition::Remote(p.to_string())))
+        .collect()
+}
+
+/// Ask the user a yes or no question, unless answered in advance with
+/// `--yes` or `--no`. Closed input answers no.
+fn confirm(question: &str, answer: Option<bool>) -> Result<bool, Error> {
+    if let Some(a) = answer {
+        return Ok(a);
+    }
+    loop {
+        println!("{}", question);
+        print!("[Y]es, [N]o: ");
+        let _ = stdout().flush();
+        let mut reply = String::new();
+        if stdin().read_line(&mut reply)? == 0 {
+            println!();
+            return Ok(false);
+        }
+        match reply.trim().to_lowercase().as_str() {
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => {}
+        }
+    }
+}
 
-                config.profiles.remove(profile);
+fn run(cli: &Cli) -> Result<(), Error> {
+    let answer = match (cli.yes, cli.no) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    };
+    let config_path = Config::detect();
+    let config_string: Option<String> = match config_path {
+        Some(ref cp) => Some(fs::read_to_string(cp).map_err(|e| Error::Io {
+            path: Some(cp.to_path_buf()),
+            source: e,
+        })?),
+        None => None,
+    };
+    let config: Option<Config> = match (&cli.command, config_string.as_ref()) {
+        // A broken configuration file can still be repaired with `gidc edit`
+        (Action::Edit { name: None }, Some(s)) => s.parse::<Config>().ok(),
+        (_, s) => s.map(|s| s.parse::<Config>()).transpose()?,
+    };
+    git::locate(config.as_ref().and_then(|c| c.git.as_deref()));
+
+    match &cli.command {
+        Action::Copy {
+            force,
+            source,
+            destination,
+        } => {
+            let config_path = config_path.as_ref().ok_or(Error::NoConfig)?;
+            let mut config = config.ok_or(Error::NoConfig)?;
+
+            let mut profile = config.profile(source)?.clone();
+            if source == destination || !may_overwrite(&config, destination, *force, answer)? {
+                return Ok(());
             }
+            profile.name = destination.to_string();
+            config.insert_profile(profile)?;
+            write_file(config_path, &config.to_string())?;
+            println!("Profile {} copied to {}", source, destination);
+        }
+        Action::Diff {
+            scope,
+            porcelain,
+            filter,
+            name,
+            other,
+        } => {
+            let config = config.ok_or(Error::NoConfig)?;
+            let profile = config.resolve_profile(&profile_name(name, &config)?)?;
 
-            let mut new_profile: Profile = Profile {
-                name: profile.to_string(),
-                fields: BTreeMap::new(),
+            // Show changes from the first profile to the second, or from Git
+            // configuration to the profile, leaving out keys describing the
+            // repository on both sides as imports do
+            let mut values = profile_values(&profile);
+            match other {
+                Some(other) => {
+                    let mut other = profile_values(&config.resolve_profile(other)?);
+                    values.retain(|key, _| filter.matches(key, &[]));
+                    other.retain(|key, _| filter.matches(key, &[]));
+                    print!("{}", render_diff(&values, &other, *porcelain))
+                }
+                None => {
+                    let mut current = scope_values(&scope.scope())?;
+                    values.retain(|key, _| filter.matches(key, IMPORT_DENYLIST));
+                    current.retain(|key, _| filter.matches(key, IMPORT_DENYLIST));
+                    print!("{}", render_diff(&current, &values, *porcelain))
+                }
+            }
+        }
+        Action::Display { resolved, name } => {
+            let config = config.ok_or(Error::NoConfig)?;
+            let profile = config.profile(&profile_name(name, &config)?)?;
+
+            if *resolved {
+                let resolved = config.resolve(&profile.name)?;
+                println!("[{}]", profile.name);
+                for (key, (source, val)) in resolved.fields.iter() {
+                    println!("{} = {}  # {}", key, val, source);
+                }
+                if !resolved.env.is_empty() {
+                    println!();
+                    println!("[{}.env]", profile.name);
+                    for (var, (source, val)) in resolved.env.iter() {
+                        match val {
+                            Some(v) => {
+                                println!("{} = {}  # {}", var, Value::String(v.to_string()), source)
+                            }
+                            None => println!("{} = false  # {}", var, source),
+                        }
+                    }
+                }
+            } else {
+                println!("{}", profile);
+            }
+        }
+        Action::Edit { name } => {
+            let config_path = config_path.as_ref().ok_or(Error::NoConfig)?;
+
+            let edited = match name {
+                Some(name) => {
+                    let config = config.ok_or(Error::NoConfig)?;
+                    edit_until_valid(&config.profile_source(name)?, |text| {
+                        config.replace_profile_source(name, text)
+                    })?
+                }
+                None => {
+                    let original = config_string.as_deref().ok_or(Error::NoConfig)?;
+                    edit_until_valid(original, |text| text.parse::<Config>())?
+                }
+            };
+            if let Some(edited) = edited {
+                write_file(config_path, &edited.to_string())?;
+                println!("Configuration written to {}", config_path.display());
+            }
+        }
+        Action::Env { shell, unset, name } => {
+            let config = config.ok_or(Error::NoConfig)?;
+            let name = profile_name(name, &config)?;
+            let profile = config.resolve_profile(&name)?;
+
+            let mut vars: Vec<(String, Option<String>)> = Vec::new();
+            if *unset {
+                if let Shell::Dotenv = shell {
+                    return Err(Error::UnsupportedValue {
+                        key: "--unset".to_string(),
+                        message: "dotenv files cannot remove variables".to_string(),
+                    });
+                }
+                for (var, val) in profile.env.iter() {
+                    if val.is_some() {
+                        vars.push((var.to_string(), None));
+                    }
+                }
+                vars.extend(config_env_changes(&[]));
+                vars.push(("GID_PROFILE".to_string(), None));
+            } else {
+                if profile.is_abstract {
+                    return Err(Error::AbstractProfile(name));
+                }
+                vars.extend(profile.env.clone());
+                vars.extend(config_env_changes(&profile.config_pairs()));
+                // Also select the profile for `gid` and `gidc` in the shell
+                vars.push(("GID_PROFILE".to_string(), Some(name)));
+            }
+            // Check every name before printing anything meant for `eval`
+            let commands = vars
+                .iter()
+                .map(|(var, val)| shell.assign(var, val.as_deref()))
+                .collect::<Result<Vec<_>, _>>()?;
+            for command in commands {
+                println!("{}", command);
+            }
+        }
+        Action::Exec { profile, command } => {
+            let config = config.ok_or(Error::NoConfig)?;
+            let selection = match profile {
+                Some(name) => config.select_named(name, Rule::Argument)?,
+                None => config.select(Repository::from_git_args::<&str>(&[]).as_ref())?,
             };
+            let profile = config.resolve_profile(&selection.profile.name)?;
 
-            let config_string: String = str::from_utf8(
-                &Command::new("git")
-                    .arg("config")
-                    .arg(if *global { "--global" } else { "--local" })
-                    .arg("--list")
-                    .output()
-                    .expect("failed to execute Git command")
-                    .stdout,
-            )
-            .expect("could not parse Git config output into valid UTF-8")
-            .to_string();
-
-            for line in config_string.lines() {
-                let (key, value) = line.split_once('=').unwrap();
-                if let Ok(v) = Value::from_git_str(value) {
-                    new_profile.fields.insert(key.to_string(), v);
-                }
-            }
-
-            config.profiles.insert(new_profile);
-            fs::write(&config_path, config.to_string()).unwrap();
-            println!(
-                "{} configuration imported to {}",
-                if *global { "Global" } else { "Local" },
-                profile
-            );
+            let mut child = process::Command::new(&command[0]);
+            child.args(&command[1..]);
+            set_env(&mut child, &profile.env);
+            child.envs(config_env(&profile.config_pairs()));
+            // Nested `gid` and `gidc` commands use a profile given explicitly,
+            // and otherwise select their own from their repository
+            if selection.rule == Rule::Argument {
+                child.env("GID_PROFILE", &profile.name);
+            }
+            let e = exec(child);
+            eprintln!("gidc: failed to execute {}: {}", command[0], e);
+            process::exit(exec_error_code(&e));
         }
-        Action::Init { config, exe, path } => {
-            let c = Config {
-                active: None,
-                profiles: BTreeSet::new(),
+        Action::Export {
+            scope,
+            dry_run,
+            force,
+            undo,
+            filter,
+            interactive,
+            name,
+        } => {
+            let config_dir = config_dir(&config_path)?;
+            let config = config.ok_or(Error::NoConfig)?;
+            let scope = scope.scope();
+            let snapshots_path = snapshot::path(&scope, config_dir)?;
+
+            let target = snapshot::target(&scope);
+
+            if *undo {
+                let mut snapshots = snapshot::read(&snapshots_path)?;
+                let Some(index) = snapshots.iter().rposition(|s| s.target == target) else {
+                    println!("No export to undo in {}", scope);
+                    return Ok(());
+                };
+                let last = &snapshots[index];
+                let current = scope_values(&scope)?;
+                for (key, values) in last.values.iter() {
+                    if current.contains_key(key) {
+                        git::run(&scope.config_args(&["--unset-all", key]))?;
+                    }
+                    for v in values.iter() {
+                        git::run(&scope.config_args(&["--add", key, v]))?;
+                    }
+                }
+                println!("Export of profile {} undone in {}", last.profile, scope);
+                snapshots.remove(index);
+                snapshot::write(&snapshots_path, &snapshots)?;
+                return Ok(());
+            }
+
+            let profile = config.resolve_profile(&profile_name(name, &config)?)?;
+            // Exports may create a standalone file
+            let current = match &scope {
+                Scope::File(path) if !path.exists() => GitValues::new(),
+                _ => scope_values(&scope)?,
             };
+            let mut new: GitValues = profile_values(&profile)
+                .into_iter()
+                .filter(|(key, values)| {
+                    current.get(key) != Some(values) && filter.matches(key, &[])
+                })
+                .collect();
+            let entries: Vec<(&str, String)> = new
+                .iter()
+                .map(|(key, values)| (key.as_str(), values.join("\n")))
+                .collect();
+            let picked: BTreeSet<String> = pick(&entries, "export", *interactive)?
+                .into_iter()
+                .map(String::from)
+                .collect();
+            new.retain(|key, _| picked.contains(key));
+            if new.is_empty() {
+                println!("Nothing to export to {}", scope);
+                return Ok(());
+            }
+            let old: GitValues = current
+                .into_iter()
+                .filter(|(key, _)| new.contains_key(key))
+                .collect();
 
-            let mut paths: Vec<PathBuf> = Vec::new();
+            if *dry_run {
+                print!("{}", render_diff(&old, &new, false));
+                return Ok(());
+            }
+            if !old.is_empty() && !*force {
+                eprint!("{}", render_diff(&old, &new, false));
+                return Err(Error::Conflicts(old.len()));
+            }
+
+            snapshot::push(
+                &snapshots_path,
+                Snapshot {
+                    profile: profile.name.to_string(),
+                    target,
+                    values: new
+                        .keys()
+                        .map(|key| (key.to_string(), old.get(key).cloned().unwrap_or_default()))
+                        .collect(),
+                },
+            )?;
+            for (key, values) in new.iter() {
+                // Replace all existing values, then add any further values of
+                // multi-valued keys
+                for (i, v) in values.iter().enumerate() {
+                    let mode = if i == 0 { "--replace-all" } else { "--add" };
+                    git::run(&scope.config_args(&[mode, key, v]))?;
+                }
+            }
+            println!("Profile {} exported to {}", profile.name, scope);
+        }
+        Action::Unexport {
+            scope,
+            dry_run,
+            if_unchanged,
+            name,
+        } => {
+            let config = config.ok_or(Error::NoConfig)?;
+            let profile = config.resolve_profile(&profile_name(name, &config)?)?;
+            let scope = scope.scope();
+
+            let values = profile_values(&profile);
+            let mut current = scope_values(&scope)?;
+            let mut removed = GitValues::new();
+            for (key, values) in values.iter() {
+                let Some(existing) = current.get(key) else {
+                    continue;
+                };
+                if *if_unchanged && existing != values {
+                    println!("Keeping {}, which differs from the profile", key);
+                    continue;
+                }
+                if let Some(existing) = current.remove(key) {
+                    removed.insert(key.to_string(), existing);
+                }
+            }
 
-            if *config {
-                if let Some(cp) = Config::config_path() {
-                    paths.push(cp);
+            if *dry_run {
+                print!("{}", render_diff(&removed, &GitValues::new(), false));
+                return Ok(());
+            }
+            for key in removed.keys() {
+                // Remove all values of multi-valued keys
+                git::run(&scope.config_args(&["--unset-all", key]))?;
+            }
+            // Git leaves section headers behind once their last key is unset
+            let sections: BTreeSet<&str> = removed
+                .keys()
+                .filter_map(|key| key.rsplit_once('.').map(|(section, _)| section))
+                .collect();
+            for section in sections {
+                let prefix = format!("{}.", section);
+                let is_empty = !current.keys().any(|key| {
+                    key.strip_prefix(&prefix)
+                        .is_some_and(|name| !name.contains('.'))
+                });
+                if is_empty {
+                    // Ignore sections Git already removed itself
+                    let _ = git::output(&scope.config_args(&["--remove-section", section]));
                 }
             }
+            println!("Profile {} unexported from {}", profile.name, scope);
+        }
+        Action::Field { action } => {
+            let config_path = config_path.as_ref().ok_or(Error::NoConfig)?;
+            let mut config = config.ok_or(Error::NoConfig)?;
+
+            match action {
+                FieldAction::Set {
+                    profile,
+                    r#type,
+                    key,
+                    value,
+                } => {
+                    let profile = profile_name(profile, &config)?;
+                    let value = match r#type {
+                        Some(t) => t.parse(key, value)?,
+                        None => Value::from_git_str(value)?,
+                    };
+                    config.set_field(&profile, key, value)?;
+                    validate_fields(&config, &profile, key)?;
+                }
+                FieldAction::Unset { profile, key } => {
+                    let profile = profile_name(profile, &config)?;
+                    if config.remove_field(&profile, key)?.is_none() {
+                        return Err(Error::UnknownField {
+                            profile,
+                            key: key.to_string(),
+                        });
+                    }
+                    validate_fields(&config, &profile, key)?;
+                }
+            }
+            write_file(config_path, &config.to_string())?;
+        }
+        Action::Get {
+            resolved,
+            profile,
+            key,
+        } => {
+            let config = config.ok_or(Error::NoConfig)?;
+            let profile = profile_name(profile, &config)?;
+
+            let value = if *resolved {
+                let fields = config.resolve(&profile)?.fields;
+                find_key(&fields, key)
+                    .and_then(|k| fields.get(k))
+                    .map(|(_, v)| (*v).clone())
+            } else {
+                let fields = &config.profile(&profile)?.fields;
+                find_key(fields, key).and_then(|k| fields.get(k)).cloned()
+            };
+            let value = value.ok_or_else(|| Error::UnknownField {
+                profile,
+                key: key.to_string(),
+            })?;
+            for v in value.values() {
+                println!("{}", v.to_git_string());
+            }
+        }
+        Action::Import {
+            filter,
+            discover: true,
+            ..
+        } => {
+            let mut config = config.ok_or(Error::NoConfig)?;
+            let discovered = discover(config_dir(&config_path)?, filter)?;
+            if discovered.is_empty() {
+                println!("No files included from global Git configuration");
+                return Ok(());
+            }
 
-            if *exe {
-                if let Some(cp) = Config::exe_path() {
-                    paths.push(cp);
+            let mut imported = Vec::new();
+            for (path, mut profile) in discovered {
+                if let Some(old_profile) = config.profiles.get(profile.name.as_str()) {
+                    if !confirm(
+                        &format!(
+                            "Profile {} already exists; overwrite while importing?",
+                            profile.name
+                        ),
+                        answer,
+                    )? {
+                        continue;
+                    }
+                    // Keep gid settings that includes cannot express
+                    profile.env = old_profile.env.clone();
+                    profile.extends = old_profile.extends.clone();
+                    profile.is_abstract = old_profile.is_abstract;
                 }
+                let name = profile.name.clone();
+                if let Err(e) = config.insert_profile(profile) {
+                    eprintln!("gidc: skipping {}: {}", path.display(), e);
+                    continue;
+                }
+                imported.push((name, path));
+            }
+            if imported.is_empty() {
+                println!("No profiles imported");
+                return Ok(());
+            }
+            write_file(
+                config_path.as_ref().ok_or(Error::NoConfig)?,
+                &config.to_string(),
+            )?;
+            for (name, path) in imported {
+                println!("Profile {} imported from {}", name, path.display());
+            }
+        }
+        Action::Import {
+            scope,
+            merge,
+            update,
+            resolve,
+            filter,
+            interactive,
+            name,
+            ..
+        } => {
+            let config_path = config_path.as_ref().ok_or(Error::NoConfig)?;
+            let mut config = config.ok_or(Error::NoConfig)?;
+            let profile = &profile_name(name, &config)?;
+
+            let mut new_profile = Profile::new(profile);
+            let combine = *merge || *update || *resolve;
+            let mut old_fields = None;
+
+            if let Some(old_profile) = config.profiles.get(profile.as_str()) {
+                if !combine
+                    && !confirm(
+                        &format!(
+                            "Profile {} already exists; overwrite while importing?",
+                            profile
+                        ),
+                        answer,
+                    )?
+                {
+                    return Ok(());
+                }
+                if combine {
+                    old_fields = Some(old_profile.fields.clone());
+                }
+
+                // Keep gid settings of the replaced profile
+                new_profile.directories = old_profile.directories.clone();
+                new_profile.remotes = old_profile.remotes.clone();
+                new_profile.env = old_profile.env.clone();
+                new_profile.extends = old_profile.extends.clone();
+                new_profile.is_abstract = old_profile.is_abstract;
+            }
+
+            new_profile.fields = read_fields(&scope.scope(), filter)?;
+
+            let entries: Vec<(&str, String)> = new_profile
+                .fields
+                .iter()
+                .map(|(key, val)| (key.as_str(), val.to_git_string()))
+                .collect();
+            let picked: BTreeSet<String> = pick(&entries, "import", *interactive)?
+                .into_iter()
+                .map(String::from)
+                .collect();
+            if *interactive && picked.is_empty() {
+                println!("No keys picked; profile {} unchanged", profile);
+                return Ok(());
+            }
+            new_profile.fields.retain(|key, _| picked.contains(key));
+
+            if let Some(mut fields) = old_fields {
+                for (key, v) in std::mem::take(&mut new_profile.fields) {
+                    // Match existing keys as Git does, keeping their spelling
+                    let existing = find_key(&fields, &key).map(String::from);
+                    let Some(existing) = existing else {
+                        fields.insert(key, v);
+                        continue;
+                    };
+                    if fields[&existing] == v || *merge {
+                        continue;
+                    }
+                    let question = format!(
+                        "{} is `{}` in profile {} and `{}` in Git configuration; \
+                         take the Git value?",
+                        existing,
+                        fields[&existing].to_git_string(),
+                        profile,
+                        v.to_git_string()
+                    );
+                    if *update || confirm(&question, answer)? {
+                        fields.insert(existing, v);
+                    }
+                }
+                new_profile.fields = fields;
+            }
+
+            config.insert_profile(new_profile)?;
+            write_file(config_path, &config.to_string())?;
+            println!("Profile {} imported from {}", profile, scope.scope());
+        }
+        Action::Init { config, exe, path } => {
+            let c = Config::default();
+
+            let mut paths: Vec<PathBuf> = Vec::new();
+
+            if *config && let Some(cp) = Config::config_path() {
+                paths.push(cp);
+            }
+
+            if *exe && let Some(cp) = Config::exe_path() {
+                paths.push(cp);
             }
 
             if let Some(p) = path {
@@ -240,17 +1684,51 @@ fn main() {
             }
 
             for path in paths.iter() {
-                if config_path != None && Some(path) == config_path.as_ref() {
+                if config_path.is_some() && Some(path) == config_path.as_ref() {
                     println!("Configuration already exists at {}", path.display());
                     continue;
                 }
-                fs::write(&path, c.to_string()).unwrap();
+                write_file(path, &c.to_string())?;
                 println!("Configuration file written to {}", path.display());
             }
         }
+        Action::Link {
+            gitdir,
+            remote,
+            name,
+        } => {
+            let config_dir = config_dir(&config_path)?;
+            let config = config.ok_or(Error::NoConfig)?;
+
+            let rules = config.profile(name)?;
+            if rules.is_abstract {
+                return Err(Error::AbstractProfile(name.to_string()));
+            }
+            // Patterns given on the command line are written as rules are
+            let mut conditions = Condition::from_patterns(gitdir, remote);
+            if conditions.is_empty() {
+                conditions = Condition::from_rules(rules);
+            }
+            if conditions.is_empty() {
+                return Err(Error::NoLinkConditions(name.to_string()));
+            }
+
+            let path = link::write(config_dir, &config.resolve_profile(name)?)?;
+            let path = path.to_string_lossy();
+            let existing = link::links(config_dir)?;
+            for condition in conditions {
+                let linked = existing
+                    .iter()
+                    .any(|l| l.profile == *name && l.condition == condition);
+                if !linked {
+                    let key = condition.key();
+                    git::run(&Scope::Global.config_args(&["--add", &key, &path]))?;
+                }
+                println!("Profile {} linked for {}", name, condition);
+            }
+        }
         Action::List => {
-            config_path.as_ref().expect(config_error_message);
-            let config = config.unwrap();
+            let config = config.ok_or(Error::NoConfig)?;
 
             let active = if let Some(a) = config.active {
                 a
@@ -260,24 +1738,254 @@ fn main() {
             for profile in config.profiles.iter() {
                 if profile.name == active {
                     println!("* {}", profile.name);
+                } else if profile.is_abstract {
+                    println!("  {} (abstract)", profile.name);
                 } else {
                     println!("  {}", profile.name);
                 }
             }
         }
+        Action::New { force, name } => {
+            let config_path = config_path.as_ref().ok_or(Error::NoConfig)?;
+            let mut config = config.ok_or(Error::NoConfig)?;
+
+            if !may_overwrite(&config, name, *force, answer)? {
+                return Ok(());
+            }
+            config.insert_profile(Profile::new(name))?;
+            write_file(config_path, &config.to_string())?;
+            println!("Profile {} created", name);
+        }
+        Action::Remove { force, name } => {
+            let config_path = config_path.as_ref().ok_or(Error::NoConfig)?;
+            let mut config = config.ok_or(Error::NoConfig)?;
+
+            config.profile(name)?;
+            if !*force && !confirm(&format!("Delete profile {}?", name), answer)? {
+                return Ok(());
+            }
+            let was_active = config.active.as_deref() == Some(name);
+            remove_profile(&mut config, name)?;
+            write_file(config_path, &config.to_string())?;
+            println!("Profile {} deleted", name);
+            if was_active {
+                println!("There is no active profile now; set one with `gidc set`");
+            }
+        }
+        Action::Rename { force, old, new } => {
+            let config_path = config_path.as_ref().ok_or(Error::NoConfig)?;
+            let mut config = config.ok_or(Error::NoConfig)?;
+
+            config.profile(old)?;
+            if old == new || !may_overwrite(&config, new, *force, answer)? {
+                return Ok(());
+            }
+            if config.profiles.contains(new.as_str()) {
+                // The replacement keeps the active profile's name in place
+                let was_active = config.active.as_deref() == Some(new);
+                remove_profile(&mut config, new)?;
+                if was_active {
+                    config.set_active(Some(new));
+                }
+            }
+            config.rename_profile(old, new)?;
+            write_file(config_path, &config.to_string())?;
+            println!("Profile {} renamed to {}", old, new);
+        }
         Action::Set { name } => {
-            config_path.as_ref().expect(config_error_message);
-            let ref config_path = config_path.unwrap();
-            let mut config = config.unwrap();
-            let mut config_doc = config_doc.unwrap();
+            let config_path = config_path.as_ref().ok_or(Error::NoConfig)?;
+            let mut config = config.ok_or(Error::NoConfig)?;
 
-            if let Some(_) = config.profiles.get(name) {
-                config.active = Some(name.to_string());
-            } else {
-                panic!("profile name not found in configuration");
+            if config.profile(name)?.is_abstract {
+                return Err(Error::AbstractProfile(name.to_string()));
+            }
+            config.set_active(Some(name));
+            write_file(config_path, &config.to_string())?;
+        }
+        Action::Sync => {
+            let config_dir = config_dir(&config_path)?;
+            let config = config.ok_or(Error::NoConfig)?;
+
+            let names: BTreeSet<String> = link::links(config_dir)?
+                .into_iter()
+                .map(|l| l.profile)
+                .collect();
+            if names.is_empty() {
+                println!("No linked profiles");
+            }
+            for name in names {
+                match config.resolve_profile(&name) {
+                    Ok(profile) => {
+                        link::write(config_dir, &profile)?;
+                        println!("Regenerated linked profile {}", name);
+                    }
+                    Err(Error::UnknownProfile(_)) => eprintln!(
+                        "gidc: linked profile '{}' no longer exists; remove it with \
+                         `gidc unlink {}`",
+                        name, name
+                    ),
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Action::Unlink {
+            gitdir,
+            remote,
+            name,
+        } => {
+            let config_dir = config_dir(&config_path)?;
+
+            // Also match includes written from the patterns as given
+            let only = [
+                Condition::from_patterns(gitdir, remote),
+                conditions(gitdir, remote),
+            ]
+            .concat();
+            let links = link::links(config_dir)?;
+            let (removed, kept): (Vec<_>, Vec<_>) = links
+                .into_iter()
+                .filter(|l| l.profile == *name)
+                .partition(|l| only.is_empty() || only.contains(&l.condition));
+            if removed.is_empty() {
+                match only.is_empty() {
+                    true => println!("Profile {} is not linked", name),
+                    false => println!("Profile {} is not linked for these conditions", name),
+                }
+                return Ok(());
+            }
+
+            for l in removed.iter() {
+                let key = l.condition.key();
+                git::run(&Scope::Global.config_args(&[
+                    "--fixed-value",
+                    "--unset-all",
+                    &key,
+                    &l.path,
+                ]))?;
+                println!("Profile {} unlinked for {}", name, l.condition);
+            }
+            // Git leaves section headers behind once their last key is unset
+            let remaining: BTreeSet<String> = git::list(&Scope::Global)?
+                .into_iter()
+                .filter_map(|e| e.key.rsplit_once('.').map(|(s, _)| s.to_lowercase()))
+                .collect();
+            for l in removed.iter() {
+                let section = format!("includeIf.{}", l.condition);
+                if !remaining.contains(&section.to_lowercase()) {
+                    let _ =
+                        git::output(&Scope::Global.config_args(&["--remove-section", &section]));
+                }
+            }
+            if kept.is_empty() {
+                link::remove(config_dir, name)?;
             }
-            config_doc["active"] = value(name);
-            fs::write(&config_path, config_doc.to_string()).unwrap();
         }
+        Action::Shim {
+            action: ShimAction::Install { dir },
+        } => {
+            let gid = env::current_exe()?.with_file_name(format!("gid{}", env::consts::EXE_SUFFIX));
+            let gid = fs::canonicalize(&gid).map_err(|e| Error::Io {
+                path: Some(gid),
+                source: e,
+            })?;
+            let shim = dir.join(format!("git{}", env::consts::EXE_SUFFIX));
+            let io_error = |source| Error::Io {
+                path: Some(shim.to_path_buf()),
+                source,
+            };
+
+            if fs::symlink_metadata(&shim).is_ok() {
+                let is_shim = fs::canonicalize(&shim).ok().as_ref() == Some(&gid)
+                    || fs::read(&shim).ok() == Some(fs::read(&gid)?);
+                if !is_shim {
+                    return Err(io_error(std::io::Error::new(
+                        std::io::ErrorKind::AlreadyExists,
+                        "file exists and is not a gid shim",
+                    )));
+                }
+                println!("Shim already installed at {}", shim.display());
+                return Ok(());
+            }
+
+            fs::create_dir_all(dir).map_err(|e| Error::Io {
+                path: Some(dir.to_path_buf()),
+                source: e,
+            })?;
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(&gid, &shim).map_err(io_error)?;
+            #[cfg(not(unix))]
+            fs::copy(&gid, &shim).map_err(io_error)?;
+            println!("Shim installed at {}", shim.display());
+
+            let dir = fs::canonicalize(dir).ok();
+            let on_path = env::var_os("PATH").is_some_and(|paths| {
+                env::split_paths(&paths).any(|p| fs::canonicalize(p).ok() == dir)
+            });
+            if !on_path {
+                println!(
+                    "Add {} to PATH before the directory of Git to use it",
+                    shim.parent().unwrap_or(&shim).display()
+                );
+            }
+        }
+        Action::Which { path } => {
+            let config = config.ok_or(Error::NoConfig)?;
+
+            let dir = match path {
+                Some(p) => PathBuf::from(p),
+                None => env::current_dir()?,
+            };
+            let repo = Repository::discover(&dir, None, None);
+            let selection = config.select(repo.as_ref())?;
+            println!("{} ({})", selection.profile.name, selection.rule);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_shell_assignments() {
+        let assign = |shell: Shell, value| shell.assign("X", value).unwrap();
+        assert_eq!(assign(Shell::Bash, Some("it's")), "export X='it'\\''s'");
+        assert_eq!(assign(Shell::Zsh, None), "unset X");
+        assert_eq!(assign(Shell::Fish, Some("a\\'b")), "set -gx X 'a\\\\\\'b'");
+        assert_eq!(assign(Shell::Powershell, Some("it's")), "$env:X = 'it''s'");
+        assert_eq!(
+            assign(Shell::Dotenv, Some("\"$HOME\"\n")),
+            "X=\"\\\"\\$HOME\\\"\\n\""
+        );
+    }
+
+    #[test]
+    fn refuses_invalid_variable_names() {
+        for shell in [Shell::Bash, Shell::Fish, Shell::Powershell, Shell::Dotenv] {
+            assert!(shell.assign("X; touch ~/pwned #", Some("1")).is_err());
+            assert!(shell.assign("1X", None).is_err());
+        }
+    }
+
+    #[test]
+    fn parses_picks() {
+        let set = |items: &[usize]| Some(items.iter().copied().collect::<BTreeSet<_>>());
+        assert_eq!(parse_picks("all", 3), set(&[0, 1, 2]));
+        assert_eq!(parse_picks("A", 3), set(&[0, 1, 2]));
+        assert_eq!(parse_picks("none", 3), set(&[]));
+        assert_eq!(parse_picks("", 3), set(&[]));
+        assert_eq!(parse_picks("1 3", 3), set(&[0, 2]));
+        assert_eq!(parse_picks("1,2-3", 5), set(&[0, 1, 2]));
+        assert_eq!(parse_picks("2-4, 4", 5), set(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn rejects_invalid_picks() {
+        assert_eq!(parse_picks("0", 3), None);
+        assert_eq!(parse_picks("4", 3), None);
+        assert_eq!(parse_picks("3-1", 3), None);
+        assert_eq!(parse_picks("2-", 3), None);
+        assert_eq!(parse_picks("x", 3), None);
     }
 }
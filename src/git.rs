@@ -0,0 +1,237 @@
+//! Invocation of the `git` command.
+
+use crate::Error;
+use std::{
+    env,
+    ffi::OsStr,
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    str,
+    sync::OnceLock,
+};
+
+/// Environment variable naming the Git executable to run.
+pub const PROGRAM_VAR: &str = "GID_GIT";
+
+/// Git executable run by [`command`], once located.
+static PROGRAM: OnceLock<PathBuf> = OnceLock::new();
+
+/// Locate the Git executable run by [`command`]: the `GID_GIT` environment
+/// variable, else `configured` (the `git` setting of the gid configuration),
+/// else the first `git` on `PATH` that is neither the current executable nor
+/// `gid`, so that `gid` installed as a `git` shim does not run itself.
+///
+/// The executable is located once; later calls return the same path.
+pub fn locate(configured: Option<&Path>) -> &'static Path {
+    PROGRAM.get_or_init(|| {
+        if let Some(path) = env::var_os(PROGRAM_VAR).filter(|p| !p.is_empty()) {
+            return PathBuf::from(path);
+        }
+        if let Some(path) = configured {
+            return path.to_path_buf();
+        }
+        // Both `gid` and `gidc` skip shims running `gid`
+        let own: Vec<PathBuf> = env::current_exe()
+            .into_iter()
+            .flat_map(|exe| {
+                let gid = exe.with_file_name(format!("gid{}", env::consts::EXE_SUFFIX));
+                [exe, gid]
+            })
+            .filter_map(|exe| fs::canonicalize(exe).ok())
+            .collect();
+        let name = format!("git{}", env::consts::EXE_SUFFIX);
+        env::var_os("PATH")
+            .iter()
+            .flat_map(env::split_paths)
+            .map(|dir| dir.join(&name))
+            .find(|path| path.is_file() && !is_copy(path, &own))
+            .unwrap_or_else(|| PathBuf::from("git"))
+    })
+}
+
+/// Check whether `path` is one of the executables `own`, through a link or as
+/// a copy.
+fn is_copy(path: &Path, own: &[PathBuf]) -> bool {
+    let Ok(canonical) = fs::canonicalize(path) else {
+        return false;
+    };
+    let len = |p: &Path| fs::metadata(p).map(|m| m.len()).ok();
+    own.iter().any(|exe| {
+        *exe == canonical || (len(exe) == len(path) && fs::read(exe).ok() == fs::read(path).ok())
+    })
+}
+
+/// Create a command running Git.
+pub fn command() -> Command {
+    Command::new(locate(None))
+}
+
+/// Render Git arguments for error messages.
+fn describe<S: AsRef<OsStr>>(args: &[S]) -> String {
+    args.iter()
+        .map(|a| a.as_ref().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Run Git with the given arguments and capture its output, failing only if
+/// Git cannot be run.
+fn capture<S: AsRef<OsStr>>(args: &[S]) -> Result<Output, Error> {
+    command().args(args).output().map_err(|e| Error::Git {
+        command: describe(args),
+        message: e.to_string(),
+    })
+}
+
+/// Create an error for an unsuccessful Git command from its output.
+fn failure<S: AsRef<OsStr>>(args: &[S], output: &Output) -> Error {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Error::Git {
+        command: describe(args),
+        message: match stderr.trim() {
+            "" => output.status.to_string(),
+            s => s.lines().next().unwrap_or(s).to_string(),
+        },
+    }
+}
+
+/// Run Git with the given arguments and return its standard output. Fails if
+/// Git cannot be run or exits unsuccessfully.
+pub fn output<S: AsRef<OsStr>>(args: &[S]) -> Result<Vec<u8>, Error> {
+    let output = capture(args)?;
+    if !output.status.success() {
+        return Err(failure(args, &output));
+    }
+    Ok(output.stdout)
+}
+
+/// Run Git with the given arguments, with output passed through to the user.
+/// Fails if Git cannot be run or exits unsuccessfully.
+pub fn run<S: AsRef<OsStr>>(args: &[S]) -> Result<(), Error> {
+    let status = command().args(args).status().map_err(|e| Error::Git {
+        command: describe(args),
+        message: e.to_string(),
+    })?;
+    if !status.success() {
+        return Err(Error::Git {
+            command: describe(args),
+            message: status.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Scope of a Git configuration file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scope {
+    /// Configuration of the current repository.
+    Local,
+    /// Configuration of the current user.
+    Global,
+    /// Configuration of all users of the system.
+    System,
+    /// Configuration of the current worktree, if enabled in the repository.
+    Worktree,
+    /// Standalone configuration file.
+    File(PathBuf),
+}
+
+impl Scope {
+    /// Arguments of `git config` selecting the scope.
+    pub fn args(&self) -> Vec<&OsStr> {
+        match self {
+            Self::Local => vec!["--local".as_ref()],
+            Self::Global => vec!["--global".as_ref()],
+            Self::System => vec!["--system".as_ref()],
+            Self::Worktree => vec!["--worktree".as_ref()],
+            Self::File(path) => vec!["--file".as_ref(), path.as_os_str()],
+        }
+    }
+
+    /// Arguments running `git config` in the scope with further arguments.
+    pub fn config_args<'a, S: AsRef<OsStr> + ?Sized>(&'a self, args: &'a [&S]) -> Vec<&'a OsStr> {
+        let mut all: Vec<&OsStr> = vec!["config".as_ref()];
+        all.extend(self.args());
+        all.extend(args.iter().map(|a| a.as_ref()));
+        all
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Local => write!(f, "local Git configuration"),
+            Self::Global => write!(f, "global Git configuration"),
+            Self::System => write!(f, "system Git configuration"),
+            Self::Worktree => write!(f, "worktree Git configuration"),
+            Self::File(path) => write!(f, "Git configuration file {}", path.display()),
+        }
+    }
+}
+
+/// Entry of a Git configuration scope.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub key: String,
+    /// Value of the entry. Keys without a value, which Git treats as true,
+    /// have no value.
+    pub value: Option<String>,
+    /// Whether the entry was valid UTF-8. Invalid entries are decoded with
+    /// replacement characters.
+    pub is_utf8: bool,
+}
+
+/// List the entries of a Git configuration scope in file order. The user,
+/// system and repository configuration files have no entries when missing,
+/// while a missing standalone file is an error.
+pub fn list(scope: &Scope) -> Result<Vec<Entry>, Error> {
+    if let Scope::File(path) = scope {
+        fs::metadata(path).map_err(|source| Error::Io {
+            path: Some(path.clone()),
+            source,
+        })?;
+    }
+    let args = scope.config_args(&["--null", "--get-regexp", "."]);
+    let output = capture(&args)?;
+    // Git exits with 1 when nothing matches, including for missing files
+    if output.status.code() == Some(1) {
+        return Ok(Vec::new());
+    }
+    if !output.status.success() {
+        return Err(failure(&args, &output));
+    }
+    Ok(output
+        .stdout
+        .split(|b| *b == 0)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            // Keys end at the first newline, values may contain more
+            let is_utf8 = str::from_utf8(entry).is_ok();
+            let entry = String::from_utf8_lossy(entry);
+            let (key, value) = match entry.split_once('\n') {
+                Some((key, value)) => (key, Some(value.to_string())),
+                None => (entry.as_ref(), None),
+            };
+            Entry {
+                key: key.to_string(),
+                value,
+                is_utf8,
+            }
+        })
+        .collect())
+}
+
+/// Normalize a configuration key as Git does, lowercasing its section and
+/// name but not its subsection.
+pub fn canonical_key(key: &str) -> String {
+    match crate::split_key(key).as_slice() {
+        [section, subsection, name] => format!(
+            "{}.{}.{}",
+            section.to_lowercase(),
+            subsection,
+            name.to_lowercase()
+        ),
+        _ => key.to_lowercase(),
+    }
+}
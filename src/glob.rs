@@ -0,0 +1,161 @@
+//! Minimal glob matching for profile selection rules.
+//!
+//! Supported syntax is `*` (any run of characters except a separator), `**`
+//! (any run of characters including separators), `?` (any single character
+//! except a separator) and `[...]` character classes (`[!...]` or `[^...]`
+//! to negate, `a-z` for ranges).
+
+/// Check whether `text` matches `pattern`, treating `/` as a path separator.
+pub fn matches_path(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches_from(&pattern, &text, Some('/'))
+}
+
+/// Check whether `text` matches `pattern` without any separator, such that
+/// `*` and `**` are equivalent.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches_from(&pattern, &text, None)
+}
+
+/// Check whether a pattern contains any glob metacharacters.
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+fn matches_from(pattern: &[char], text: &[char], separator: Option<char>) -> bool {
+    let Some(&p) = pattern.first() else {
+        return text.is_empty();
+    };
+
+    match p {
+        '*' => {
+            let double = pattern.get(1) == Some(&'*');
+            let rest = if double { &pattern[2..] } else { &pattern[1..] };
+            // A `**/` component may also match zero directories
+            if double
+                && separator.is_some()
+                && rest.first().copied() == separator
+                && matches_from(&rest[1..], text, separator)
+            {
+                return true;
+            }
+            for i in 0..=text.len() {
+                if matches_from(rest, &text[i..], separator) {
+                    return true;
+                }
+                if i < text.len() && !double && Some(text[i]) == separator {
+                    break;
+                }
+            }
+            false
+        }
+        '?' => match text.first() {
+            Some(&c) if Some(c) != separator => matches_from(&pattern[1..], &text[1..], separator),
+            _ => false,
+        },
+        '[' => {
+            let Some(&c) = text.first() else {
+                return false;
+            };
+            match match_class(&pattern[1..], c) {
+                Some((matched, consumed)) => {
+                    matched
+                        && Some(c) != separator
+                        && matches_from(&pattern[1 + consumed..], &text[1..], separator)
+                }
+                // Unterminated class, match `[` literally
+                None => c == '[' && matches_from(&pattern[1..], &text[1..], separator),
+            }
+        }
+        _ => match text.first() {
+            Some(&c) if c == p => matches_from(&pattern[1..], &text[1..], separator),
+            _ => false,
+        },
+    }
+}
+
+/// Match a character against a class body (the pattern after `[`). Returns
+/// whether the character matched and the number of pattern characters used,
+/// including the closing `]`.
+fn match_class(class: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let negate = matches!(class.first(), Some('!') | Some('^'));
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    while i < class.len() {
+        if class[i] == ']' && !first {
+            return Some((matched != negate, i + 1));
+        }
+        first = false;
+        if i + 2 < class.len() && class[i + 1] == '-' && class[i + 2] != ']' {
+            if class[i] <= c && c <= class[i + 2] {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if class[i] == c {
+                matched = true;
+            }
+            i += 1;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_stays_within_directory() {
+        assert!(matches_path("/srv/acme-*", "/srv/acme-web"));
+        assert!(!matches_path("/srv/acme-*", "/srv/acme-web/sub"));
+        assert!(matches("user.*", "user.signingkey"));
+        assert!(matches("url.*", "url.https://example.com/.insteadof"));
+    }
+
+    #[test]
+    fn double_star_matches_zero_or_more_directories() {
+        assert!(matches_path("**/repo", "repo"));
+        assert!(matches_path("**/repo", "/home/me/repo"));
+        assert!(matches_path("/home/**/repo", "/home/repo"));
+        assert!(matches_path("/home/**/repo", "/home/me/src/repo"));
+        assert!(matches_path("/home/me/**", "/home/me/a/b"));
+        assert!(!matches_path("/home/**/repo", "/srv/repo"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(matches_path("repo-?", "repo-1"));
+        assert!(!matches_path("repo-?", "repo-12"));
+        assert!(!matches_path("a?b", "a/b"));
+    }
+
+    #[test]
+    fn character_classes() {
+        assert!(matches_path("repo-[0-9]", "repo-7"));
+        assert!(!matches_path("repo-[0-9]", "repo-x"));
+        assert!(matches_path("repo-[!0-9]", "repo-x"));
+        assert!(matches_path("repo-[^0-9]", "repo-x"));
+        assert!(matches_path("[]a]", "]"));
+        assert!(matches_path("[ab-]", "-"));
+        assert!(!matches_path("a[/]b", "a/b"));
+        // Unterminated classes match literally
+        assert!(matches_path("repo[", "repo["));
+    }
+
+    #[test]
+    fn detects_glob_patterns() {
+        assert!(is_glob("acme-*"));
+        assert!(is_glob("repo-?"));
+        assert!(is_glob("[ab]"));
+        assert!(!is_glob("github.com/acme"));
+    }
+}
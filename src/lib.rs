@@ -1,30 +1,87 @@
-use serde::de::Error;
+mod document;
+mod error;
+pub mod git;
+pub mod glob;
+pub mod link;
+pub mod process;
+pub mod repo;
+pub mod snapshot;
+
+pub use error::Error;
+use repo::{Repository, directory_matches, expand_directory_pattern, home_dir, remote_matches};
 use std::{
     borrow::Borrow,
     cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd},
     collections::{BTreeMap, BTreeSet},
-    env, fs,
-    io::Write,
+    env, fmt, fs,
     path::{Path, PathBuf},
-    str::{from_utf8, FromStr},
-    string::ToString,
+    str::FromStr,
 };
-use toml;
+use toml_edit::DocumentMut;
 
 pub trait ToGitString {
     fn to_git_string(&self) -> String;
 }
 
 pub trait FromGitStr {
-    fn from_git_str(s: &str) -> Result<Self, String>
+    fn from_git_str(s: &str) -> Result<Self, Error>
     where
         Self: Sized;
 }
 
-#[derive(Debug)]
+/// Parsed gid configuration.
+///
+/// The configuration keeps the document it was parsed from, and renders that
+/// document with [`ToString`]. Changes made through [`Config::set_active`],
+/// [`Config::insert_profile`], [`Config::remove_profile`],
+/// [`Config::rename_profile`], [`Config::set_field`] and
+/// [`Config::remove_field`] are mirrored into the document while preserving
+/// its comments, whitespace and ordering; direct changes to the public fields
+/// are not.
+#[derive(Debug, Default)]
 pub struct Config {
     pub active: Option<String>,
+    pub injection: Injection,
+    /// Git executable to run, instead of the first `git` found on `PATH`.
+    pub git: Option<PathBuf>,
     pub profiles: BTreeSet<Profile>,
+    document: DocumentMut,
+}
+
+/// How `gid` passes profile configuration to Git.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Injection {
+    /// Prepend `-c key=value` arguments.
+    #[default]
+    Arguments,
+    /// Set `GIT_CONFIG_COUNT`, `GIT_CONFIG_KEY_<n>` and `GIT_CONFIG_VALUE_<n>`
+    /// environment variables, which keeps values out of the process list and
+    /// is inherited by processes Git spawns.
+    Environment,
+}
+
+impl FromStr for Injection {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "args" => Ok(Self::Arguments),
+            "env" => Ok(Self::Environment),
+            _ => Err(unsupported(
+                "injection",
+                &format!("unknown mode '{}', expected \"args\" or \"env\"", s),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Injection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Arguments => write!(f, "args"),
+            Self::Environment => write!(f, "env"),
+        }
+    }
 }
 
 impl Config {
@@ -41,14 +98,7 @@ impl Config {
 
     /// Get path of config file in user config directory, if path exists.
     pub fn config_path() -> Option<PathBuf> {
-        let home_env = if env::consts::OS == "windows" {
-            "USERPROFILE"
-        } else {
-            "HOME"
-        };
-        let home_path = env::var(home_env);
-        if let Ok(s) = home_path {
-            let mut config_path = PathBuf::from(&s);
+        if let Some(mut config_path) = home_dir() {
             config_path.push(".config");
             config_path.push("gid");
             config_path.push("gid.toml");
@@ -72,160 +122,717 @@ impl Config {
         // Check current executable directory for config file
         if config_path.is_none() {
             let local_config_path = Config::exe_path();
-            if let Some(ref p) = local_config_path {
-                if let Ok(true) = p.try_exists() {
-                    config_path = local_config_path;
-                }
+            if let Some(ref p) = local_config_path
+                && let Ok(true) = p.try_exists()
+            {
+                config_path = local_config_path;
             }
         }
 
         // Check user config directory for config file
         if config_path.is_none() {
             let config_config_path = Config::config_path();
-            if let Some(ref p) = config_config_path {
-                if let Ok(true) = p.try_exists() {
-                    config_path = config_config_path;
-                }
+            if let Some(ref p) = config_config_path
+                && let Ok(true) = p.try_exists()
+            {
+                config_path = config_config_path;
             }
         }
         config_path
     }
 
-    pub fn parse_file(p: &Path) -> Result<Self, String> {
-        let config_string = fs::read_to_string(p);
-        if config_string.is_err() {
-            return Err("could not load configuration file".to_string());
+    /// Get session profile override from the `GID_PROFILE` environment
+    /// variable, if set and not empty.
+    pub fn profile_override() -> Option<String> {
+        env::var("GID_PROFILE").ok().filter(|s| !s.is_empty())
+    }
+
+    /// Name of the profile used when no profile is given explicitly: the
+    /// `GID_PROFILE` session override, or else the active profile.
+    pub fn current(&self) -> Option<String> {
+        Config::profile_override().or_else(|| self.active.clone())
+    }
+
+    /// Select the profile for a Git command run in `repo`. The `GID_PROFILE`
+    /// session override takes priority, followed by profiles with a matching
+    /// directory rule, profiles with a matching remote rule, and finally the
+    /// active profile. If several rules match, the most specific (longest)
+    /// pattern wins.
+    ///
+    /// Remotes are considered one at a time, `origin` first and the rest in
+    /// name order; the first remote matching any rule decides the profile.
+    pub fn select(&self, repo: Option<&Repository>) -> Result<Selection<'_>, Error> {
+        if let Some(name) = Config::profile_override() {
+            return self.select_named(&name, Rule::Environment);
+        }
+
+        if let Some(repo) = repo {
+            let roots = repo.root_variants();
+            let mut best: Option<(usize, Selection)> = None;
+            for profile in self.profiles.iter().filter(|p| !p.is_abstract) {
+                for pattern in profile.directories.iter() {
+                    let expanded = expand_directory_pattern(pattern);
+                    if !roots.iter().any(|r| directory_matches(&expanded, r)) {
+                        continue;
+                    }
+                    if best.as_ref().is_none_or(|(len, _)| expanded.len() > *len) {
+                        best = Some((
+                            expanded.len(),
+                            Selection {
+                                profile,
+                                rule: Rule::Directory(pattern.to_string()),
+                            },
+                        ));
+                    }
+                }
+            }
+            if let Some((_, selection)) = best {
+                return Ok(selection);
+            }
+
+            if self.profiles.iter().any(|p| !p.remotes.is_empty()) {
+                for (remote, url) in repo.remotes() {
+                    let mut best: Option<(usize, Selection)> = None;
+                    for profile in self.profiles.iter().filter(|p| !p.is_abstract) {
+                        for pattern in profile.remotes.iter() {
+                            if !remote_matches(pattern, &url) {
+                                continue;
+                            }
+                            if best.as_ref().is_none_or(|(len, _)| pattern.len() > *len) {
+                                best = Some((
+                                    pattern.len(),
+                                    Selection {
+                                        profile,
+                                        rule: Rule::Remote {
+                                            pattern: pattern.to_string(),
+                                            remote: remote.to_string(),
+                                            url: url.to_string(),
+                                        },
+                                    },
+                                ));
+                            }
+                        }
+                    }
+                    if let Some((_, selection)) = best {
+                        return Ok(selection);
+                    }
+                }
+            }
+        }
+
+        let active = self.active.as_ref().ok_or(Error::NoActiveProfile)?;
+        self.select_named(active, Rule::Active)
+    }
+
+    /// Select a profile by name, for the given reason.
+    pub fn select_named(&self, name: &str, rule: Rule) -> Result<Selection<'_>, Error> {
+        match self.profiles.get(name) {
+            Some(profile) if profile.is_abstract => Err(Error::AbstractProfile(name.to_string())),
+            Some(profile) => Ok(Selection { profile, rule }),
+            None => Err(Error::UnknownProfile(name.to_string())),
+        }
+    }
+
+    /// Get a profile by name.
+    pub fn profile(&self, name: &str) -> Result<&Profile, Error> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| Error::UnknownProfile(name.to_string()))
+    }
+
+    /// Check that every parent profile exists and that there are no
+    /// inheritance cycles.
+    fn check_inheritance(&self) -> Result<(), Error> {
+        for profile in self.profiles.iter() {
+            for parent in profile.extends.iter() {
+                if !self.profiles.contains(parent) {
+                    return Err(Error::Inheritance(format!(
+                        "profile '{}' extends unknown profile '{}'",
+                        profile.name, parent
+                    )));
+                }
+            }
+        }
+
+        // Depth-first search, `path` holds the current chain of profiles
+        fn visit<'a>(
+            config: &'a Config,
+            name: &'a str,
+            path: &mut Vec<&'a str>,
+            done: &mut BTreeSet<&'a str>,
+        ) -> Result<(), Error> {
+            if let Some(i) = path.iter().position(|p| *p == name) {
+                let mut cycle = path[i..].to_vec();
+                cycle.push(name);
+                return Err(Error::Inheritance(format!(
+                    "profile inheritance cycle: {}",
+                    cycle.join(" -> ")
+                )));
+            }
+            if done.contains(name) {
+                return Ok(());
+            }
+            path.push(name);
+            if let Some(profile) = config.profiles.get(name) {
+                for parent in profile.extends.iter() {
+                    visit(config, parent, path, done)?;
+                }
+            }
+            path.pop();
+            done.insert(name);
+            Ok(())
+        }
+
+        let mut done = BTreeSet::new();
+        for profile in self.profiles.iter() {
+            visit(self, &profile.name, &mut Vec::new(), &mut done)?;
+        }
+        Ok(())
+    }
+
+    /// Get effective fields and environment variables of a profile including
+    /// those inherited from parent profiles, along with the name of the
+    /// profile each value comes from. Parents are merged in the order they
+    /// are listed, with later parents and the profile itself taking
+    /// precedence.
+    pub fn resolve(&self, name: &str) -> Result<Resolved<'_>, Error> {
+        let profile = self.profile(name)?;
+
+        let mut result = Resolved {
+            fields: BTreeMap::new(),
+            env: BTreeMap::new(),
+        };
+        for parent in profile.extends.iter() {
+            let inherited = self.resolve(parent)?;
+            result.fields.extend(inherited.fields);
+            result.env.extend(inherited.env);
+        }
+        for (key, val) in profile.fields.iter() {
+            result.fields.insert(key.to_string(), (&profile.name, val));
+        }
+        for (var, val) in profile.env.iter() {
+            result.env.insert(var.to_string(), (&profile.name, val));
+        }
+        Ok(result)
+    }
+
+    /// Get a profile with inherited fields and environment variables merged
+    /// into its own.
+    pub fn resolve_profile(&self, name: &str) -> Result<Profile, Error> {
+        let profile = self.profile(name)?;
+        let resolved = self.resolve(name)?;
+
+        let mut result = Profile::new(name);
+        result.directories = profile.directories.clone();
+        result.remotes = profile.remotes.clone();
+        result.extends = profile.extends.clone();
+        result.is_abstract = profile.is_abstract;
+        for (key, (_, val)) in resolved.fields {
+            result.fields.insert(key, val.clone());
+        }
+        for (var, (_, val)) in resolved.env {
+            result.env.insert(var, val.clone());
         }
-        let config_string = config_string.unwrap();
+        Ok(result)
+    }
+
+    /// Set or clear the active profile.
+    pub fn set_active(&mut self, name: Option<&str>) {
+        self.active = name.map(String::from);
+        document::set_root(&mut self.document, "active", name.map(Into::into));
+    }
+
+    /// Insert a profile, replacing and returning any existing profile of the
+    /// same name. Entries of a replaced profile that did not change keep
+    /// their formatting.
+    pub fn insert_profile(&mut self, profile: Profile) -> Result<Option<Profile>, Error> {
+        check_profile_name(&profile.name)?;
+        let old = self.profiles.take(profile.name.as_str());
+        document::sync_profile(&mut self.document, old.as_ref(), &profile);
+        self.profiles.insert(profile);
+        Ok(old)
+    }
 
-        match config_string.parse::<Config>() {
-            Ok(res) => Ok(res),
-            Err(e) => Err(e.to_string()),
+    /// Remove and return a profile, clearing the active profile if it was
+    /// the removed one.
+    pub fn remove_profile(&mut self, name: &str) -> Option<Profile> {
+        let old = self.profiles.take(name)?;
+        self.document.remove(name);
+        if self.active.as_deref() == Some(name) {
+            self.set_active(None);
         }
+        Some(old)
+    }
+
+    /// Rename a profile, keeping its place and formatting in the document,
+    /// and update the active profile and parent references of other profiles
+    /// to match. Fails if the new name is taken.
+    pub fn rename_profile(&mut self, old: &str, new: &str) -> Result<(), Error> {
+        check_profile_name(new)?;
+        if self.profiles.contains(new) {
+            return Err(Error::ProfileExists(new.to_string()));
+        }
+        let mut profile = self
+            .profiles
+            .take(old)
+            .ok_or_else(|| Error::UnknownProfile(old.to_string()))?;
+        profile.name = new.to_string();
+        if let Some(item) = self.document.remove(old) {
+            self.document.insert(new, item);
+        }
+        self.profiles.insert(profile);
+
+        let children: Vec<String> = self.children(old).into_iter().map(String::from).collect();
+        for name in children {
+            if let Some(mut child) = self.profiles.take(name.as_str()) {
+                for parent in child.extends.iter_mut().filter(|e| *e == old) {
+                    *parent = new.to_string();
+                }
+                self.profiles.insert(child);
+            }
+            document::rename_parent(&mut self.document, &name, old, new);
+        }
+
+        if self.active.as_deref() == Some(old) {
+            self.set_active(Some(new));
+        }
+        Ok(())
+    }
+
+    /// Names of profiles that directly extend the given profile.
+    pub fn children(&self, name: &str) -> Vec<&str> {
+        self.profiles
+            .iter()
+            .filter(|p| p.extends.iter().any(|e| e == name))
+            .map(|p| p.name.as_str())
+            .collect()
+    }
+
+    /// Set a Git configuration field of a profile. The key must have at
+    /// least a section and a name, and must not collide with gid settings.
+    /// An existing key matching it as in Git is replaced.
+    pub fn set_field(&mut self, profile: &str, key: &str, value: Value) -> Result<(), Error> {
+        let segments = split_key(key);
+        if segments.len() < 2 || segments.iter().any(|s| s.is_empty()) || segments[0] == "env" {
+            return Err(unsupported(key, "not a Git configuration key"));
+        }
+        let mut p = self
+            .profiles
+            .take(profile)
+            .ok_or_else(|| Error::UnknownProfile(profile.to_string()))?;
+        // Replace an existing key as Git matches it, keeping its spelling
+        let key = find_key(&p.fields, key).unwrap_or(key).to_string();
+        document::set_field(&mut self.document, profile, &key, &value);
+        p.fields.insert(key, value);
+        self.profiles.insert(p);
+        Ok(())
+    }
+
+    /// Remove a Git configuration field of a profile matching the key as in
+    /// Git, returning its value.
+    pub fn remove_field(&mut self, profile: &str, key: &str) -> Result<Option<Value>, Error> {
+        let mut p = self
+            .profiles
+            .take(profile)
+            .ok_or_else(|| Error::UnknownProfile(profile.to_string()))?;
+        let key = find_key(&p.fields, key).unwrap_or(key).to_string();
+        let old = p.fields.remove(&key);
+        if old.is_some() {
+            document::remove_field(&mut self.document, profile, &key);
+        }
+        self.profiles.insert(p);
+        Ok(old)
+    }
+
+    /// Render the section of a profile on its own, as it appears in the
+    /// configuration file.
+    pub fn profile_source(&self, name: &str) -> Result<String, Error> {
+        document::profile_source(&self.document, name)
+            .ok_or_else(|| Error::UnknownProfile(name.to_string()))
+    }
+
+    /// Parse the configuration resulting from replacing the section of a
+    /// profile with `source`, which must define only that profile.
+    pub fn replace_profile_source(&self, name: &str, source: &str) -> Result<Config, Error> {
+        let table = source
+            .parse::<toml::Table>()
+            .map_err(|e| Error::from_toml(e, source))?;
+        if let Some(key) = table.keys().find(|k| *k != name) {
+            return Err(unsupported(
+                key,
+                &format!("only profile `{}` can be defined here", name),
+            ));
+        }
+        let mut edited = source.parse::<DocumentMut>().map_err(|e| Error::Syntax {
+            message: e.message().trim().to_string(),
+            line: None,
+            column: None,
+        })?;
+        let item = edited
+            .remove(name)
+            .filter(|i| i.is_table_like())
+            .ok_or_else(|| Error::UnknownProfile(name.to_string()))?;
+
+        let mut document = self.document.clone();
+        document::replace_profile(&mut document, name, item);
+        document.to_string().parse::<Config>()
+    }
+
+    pub fn parse_file(p: &Path) -> Result<Self, Error> {
+        let config_string = fs::read_to_string(p).map_err(|e| Error::Io {
+            path: Some(p.to_path_buf()),
+            source: e,
+        })?;
+        config_string.parse::<Config>()
     }
 }
 
 impl FromStr for Config {
-    type Err = toml::de::Error;
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let s_parse = s.parse::<toml::Value>();
-        match s_parse {
-            Ok(mut v) => {
-                if let Some(s_table) = v.as_table_mut() {
-                    let mut result = Config {
-                        active: None,
-                        profiles: BTreeSet::new(),
-                    };
-
-                    // Parse active profile
-                    if let Some(s) = s_table.remove("active") {
-                        if let Some(sv) = s.as_str() {
-                            result.active = Some(String::from(sv));
+        let mut v = s
+            .parse::<toml::Value>()
+            .map_err(|e| Error::from_toml(e, s))?;
+        let Some(s_table) = v.as_table_mut() else {
+            return Err(Error::Syntax {
+                message: "config is not a top level table".to_string(),
+                line: None,
+                column: None,
+            });
+        };
+        let mut result = Config {
+            document: s.parse::<DocumentMut>().map_err(|e| Error::Syntax {
+                message: e.message().trim().to_string(),
+                line: None,
+                column: None,
+            })?,
+            ..Default::default()
+        };
+
+        // Parse active profile
+        if let Some(a) = s_table.remove("active") {
+            let a = a
+                .as_str()
+                .ok_or_else(|| unsupported("active", "must be a string"))?;
+            result.active = Some(String::from(a));
+        }
+
+        // Parse injection mode
+        if let Some(i) = s_table.remove("injection") {
+            result.injection = i
+                .as_str()
+                .ok_or_else(|| unsupported("injection", "must be a string"))?
+                .parse::<Injection>()?;
+        }
+
+        // Parse Git executable
+        if let Some(g) = s_table.remove("git") {
+            let g = g
+                .as_str()
+                .ok_or_else(|| unsupported("git", "must be a string"))?;
+            result.git = Some(PathBuf::from(g));
+        }
+
+        // Parse profiles
+        for (name, profile) in s_table.iter() {
+            if let Some(pt) = profile.as_table() {
+                let mut result_profile = Profile::new(name);
+                let mut field_queue: Vec<(String, &toml::Value)> = Vec::new();
+                for (key, val) in pt.iter() {
+                    let path = format!("{}.{}", name, key);
+                    match key.as_str() {
+                        "directories" => {
+                            result_profile.directories = parse_string_list(&path, val)?;
+                            continue;
+                        }
+                        "remotes" => {
+                            result_profile.remotes = parse_string_list(&path, val)?;
+                            continue;
+                        }
+                        "env" => {
+                            result_profile.env = parse_env(&path, val)?;
+                            continue;
+                        }
+                        "extends" => {
+                            result_profile.extends = parse_string_list(&path, val)?;
+                            continue;
+                        }
+                        "abstract" => {
+                            result_profile.is_abstract = val
+                                .as_bool()
+                                .ok_or_else(|| unsupported(&path, "must be a boolean"))?;
+                            continue;
                         }
+                        _ => {}
                     }
-
-                    // Parse profiles
-                    for (name, profile) in s_table.iter() {
-                        if let Some(pt) = profile.as_table() {
-                            let mut result_profile: Profile = Profile {
-                                name: name.to_string(),
-                                fields: BTreeMap::new(),
-                            };
-                            let mut field_queue: Vec<(String, &toml::Value)> = Vec::new();
-                            for (key, val) in pt.iter() {
-                                field_queue.push((key.to_string(), val));
-                            }
-                            while field_queue.len() > 0 {
-                                let (key, val) = field_queue.pop().unwrap();
-                                match val {
-                                    toml::Value::Table(t) => {
-                                        for (tkey, tval) in t.iter() {
-                                            field_queue.push((format!("{}.{}", key, tkey), tval));
-                                        }
-                                    }
-                                    toml::Value::Array(a) => {
-                                        let mut color_array: Vec<Color> = Vec::new();
-                                        for c in a.iter() {
-                                            match c {
-                                                toml::Value::String(s) => {
-                                                    color_array.push(Color::String(s.to_string()));
-                                                }
-                                                toml::Value::Integer(i) => {
-                                                    color_array.push(Color::Number(*i as u32));
-                                                }
-                                                _ => {
-                                                    return Err(toml::de::Error::custom(
-                                                        "configuration cannot \
-                                                    contain non-color arrays \
-                                                    (colors are 0-255, 24 bit \
-                                                     hex codes, or color name \
-                                                     and attribute strings as \
-                                                     defined by Git \
-                                                     configuration values",
-                                                    ));
-                                                }
-                                            }
-                                        }
-                                        result_profile
-                                            .fields
-                                            .insert(key, Value::ColorArray(color_array));
-                                    }
-                                    toml::Value::Boolean(b) => {
-                                        result_profile.fields.insert(key, Value::Boolean(*b));
-                                    }
-                                    toml::Value::Integer(i) => {
-                                        result_profile.fields.insert(key, Value::Integer(*i));
-                                    }
-                                    toml::Value::String(s) => {
-                                        result_profile
-                                            .fields
-                                            .insert(key, Value::String(s.to_string()));
-                                    }
-                                    _ => {
-                                        return Err(toml::de::Error::custom(
-                                            "unknown Git representation for \
-                                            TOML float, date, time, and \
-                                            datetime types",
-                                        ));
-                                    }
+                    field_queue.push((key.to_string(), val));
+                }
+                while let Some((key, val)) = field_queue.pop() {
+                    let value =
+                        parse_value(&key, val).map_err(|message| Error::UnsupportedValue {
+                            key: format!("{}.{}", name, key),
+                            message,
+                        })?;
+                    match value {
+                        Some(v) => {
+                            result_profile.fields.insert(key, v);
+                        }
+                        None => {
+                            if let toml::Value::Table(t) = val {
+                                for (tkey, tval) in t.iter() {
+                                    field_queue.push((format!("{}.{}", key, tkey), tval));
                                 }
                             }
-                            result.profiles.insert(result_profile);
                         }
                     }
-                    return Ok(result);
-                } else {
-                    return Err(toml::de::Error::custom(
-                        "config is not a top level table".to_string(),
-                    ));
                 }
+                result.profiles.insert(result_profile);
             }
-            Err(e) => Err(e),
         }
+        result.check_inheritance()?;
+        Ok(result)
     }
 }
 
-impl ToString for Config {
-    fn to_string(&self) -> String {
-        let mut result = Vec::new();
-        if let Some(s) = &self.active {
-            writeln!(&mut result, "active = \"{}\"", s).unwrap();
+/// Parse a TOML value of a profile field into a Git value. Returns `None`
+/// for tables, which hold further fields.
+fn parse_value(key: &str, val: &toml::Value) -> Result<Option<Value>, String> {
+    match val {
+        toml::Value::Table(_) => Ok(None),
+        toml::Value::Array(a) if is_color_key(key) => {
+            let mut color_array: Vec<Color> = Vec::new();
+            for c in a.iter() {
+                match c {
+                    toml::Value::String(s) => {
+                        color_array.push(Color::String(s.to_string()));
+                    }
+                    toml::Value::Integer(i) => {
+                        color_array.push(Color::Number(*i as u32));
+                    }
+                    _ => {
+                        return Err("color arrays can only contain 0-255, 24 bit hex codes, \
+                                    or color name and attribute strings as defined by Git \
+                                    configuration values"
+                            .to_string());
+                    }
+                }
+            }
+            Ok(Some(Value::ColorArray(color_array)))
         }
-        for profile in self.profiles.iter() {
-            writeln!(&mut result, "").unwrap();
-            write!(&mut result, "{}", profile.to_string()).unwrap();
+        toml::Value::Array(a) => {
+            let mut values: Vec<Value> = Vec::new();
+            for v in a.iter() {
+                match v {
+                    toml::Value::Boolean(b) => values.push(Value::Boolean(*b)),
+                    toml::Value::Integer(i) => values.push(Value::Integer(*i)),
+                    toml::Value::String(s) => values.push(Value::String(s.to_string())),
+                    _ => {
+                        return Err(
+                            "multi-valued keys can only contain booleans, integers and strings"
+                                .to_string(),
+                        );
+                    }
+                }
+            }
+            Ok(Some(Value::Multiple(values)))
         }
-        from_utf8(&result).unwrap().to_string()
+        toml::Value::Boolean(b) => Ok(Some(Value::Boolean(*b))),
+        toml::Value::Integer(i) => Ok(Some(Value::Integer(*i))),
+        toml::Value::String(s) => Ok(Some(Value::String(s.to_string()))),
+        toml::Value::Float(_) | toml::Value::Datetime(_) => Err(
+            "unknown Git representation for TOML float, date, time, and datetime types".to_string(),
+        ),
     }
 }
 
+/// Check that a profile name does not collide with top level settings.
+fn check_profile_name(name: &str) -> Result<(), Error> {
+    match name {
+        "" | "active" | "injection" | "git" => Err(Error::InvalidProfileName(name.to_string())),
+        _ => Ok(()),
+    }
+}
+
+/// Create an unsupported value error.
+fn unsupported(key: &str, message: &str) -> Error {
+    Error::UnsupportedValue {
+        key: key.to_string(),
+        message: message.to_string(),
+    }
+}
+
+/// Check whether a Git configuration key holds a color, in which case TOML
+/// arrays are parsed as a single color value rather than multiple values.
+pub fn is_color_key(key: &str) -> bool {
+    // Two part keys such as `color.ui` switch colors on or off
+    match split_key(key).as_slice() {
+        [section, _, name] => {
+            section.eq_ignore_ascii_case("color") && !name.eq_ignore_ascii_case("highlightRecent")
+        }
+        _ => false,
+    }
+}
+
+/// Parse a string or array of strings gid setting.
+fn parse_string_list(key: &str, val: &toml::Value) -> Result<Vec<String>, Error> {
+    match val {
+        toml::Value::String(s) => Ok(vec![s.to_string()]),
+        toml::Value::Array(a) => a
+            .iter()
+            .map(|v| {
+                v.as_str()
+                    .map(String::from)
+                    .ok_or_else(|| unsupported(key, "must only contain strings"))
+            })
+            .collect(),
+        _ => Err(unsupported(key, "must be a string or array of strings")),
+    }
+}
+
+/// Check whether a name is a portable environment variable name, made of
+/// ASCII letters, digits and underscores and not starting with a digit.
+pub fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parse a profile's environment variable table. Strings and integers set a
+/// variable, `false` unsets it.
+fn parse_env(key: &str, val: &toml::Value) -> Result<BTreeMap<String, Option<String>>, Error> {
+    let t = val
+        .as_table()
+        .ok_or_else(|| unsupported(key, "must be a table"))?;
+    let mut result = BTreeMap::new();
+    for (name, v) in t.iter() {
+        if !is_env_name(name) {
+            return Err(unsupported(
+                &format!("{}.{}", key, name),
+                "environment variable names may only contain ASCII letters, digits and \
+                 underscores, and may not start with a digit",
+            ));
+        }
+        let v = match v {
+            toml::Value::String(s) => Some(s.to_string()),
+            toml::Value::Integer(i) => Some(i.to_string()),
+            toml::Value::Boolean(false) => None,
+            _ => {
+                return Err(unsupported(
+                    &format!("{}.{}", key, name),
+                    "environment variables must be a string, an integer, or `false` to \
+                     unset them",
+                ));
+            }
+        };
+        result.insert(name.to_string(), v);
+    }
+    Ok(result)
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.document)
+    }
+}
+
+/// Profile selected for a Git command.
+#[derive(Debug)]
+pub struct Selection<'a> {
+    pub profile: &'a Profile,
+    pub rule: Rule,
+}
+
+/// Effective values of a profile, keyed by Git configuration key or
+/// environment variable name, with the name of the profile defining each.
 #[derive(Debug)]
+pub struct Resolved<'a> {
+    pub fields: BTreeMap<String, (&'a str, &'a Value)>,
+    pub env: BTreeMap<String, (&'a str, &'a Option<String>)>,
+}
+
+/// Reason a profile was selected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    /// Profile was requested on the `gid` command line.
+    Argument,
+    /// Profile was requested with the `GID_PROFILE` environment variable.
+    Environment,
+    /// Repository root matched the contained directory pattern.
+    Directory(String),
+    /// URL of a repository remote matched a remote pattern.
+    Remote {
+        pattern: String,
+        remote: String,
+        url: String,
+    },
+    /// Profile is the configured active profile.
+    Active,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Argument => write!(f, "--gid-profile argument"),
+            Self::Environment => write!(f, "GID_PROFILE environment variable"),
+            Self::Directory(p) => write!(f, "directory rule `{}`", p),
+            Self::Remote {
+                pattern,
+                remote,
+                url,
+            } => write!(f, "remote rule `{}` matched by {} {}", pattern, remote, url),
+            Self::Active => write!(f, "active profile"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Profile {
     pub name: String,
+    /// Repository root patterns that automatically select this profile.
+    pub directories: Vec<String>,
+    /// Remote URL patterns that automatically select this profile.
+    pub remotes: Vec<String>,
+    /// Environment variables for the Git process; `None` unsets a variable.
+    pub env: BTreeMap<String, Option<String>>,
+    /// Parent profiles whose fields and environment variables are inherited.
+    pub extends: Vec<String>,
+    /// Abstract profiles only serve as parents and cannot be selected.
+    pub is_abstract: bool,
     pub fields: BTreeMap<String, Value>,
 }
 
+impl Profile {
+    /// Create an empty profile.
+    pub fn new(name: &str) -> Self {
+        Profile {
+            name: name.to_string(),
+            directories: Vec::new(),
+            remotes: Vec::new(),
+            env: BTreeMap::new(),
+            extends: Vec::new(),
+            is_abstract: false,
+            fields: BTreeMap::new(),
+        }
+    }
+}
+
+impl Profile {
+    /// Git configuration key and value pairs of the profile, with values
+    /// rendered as Git strings. Multi-valued keys produce one pair per value.
+    pub fn config_pairs(&self) -> Vec<(String, String)> {
+        let mut result = Vec::new();
+        for (key, val) in self.fields.iter() {
+            for item in val.values() {
+                result.push((key.to_string(), item.to_git_string()));
+            }
+        }
+        result
+    }
+}
+
 impl Borrow<str> for Profile {
     fn borrow(&self) -> &str {
         &self.name
@@ -254,37 +861,123 @@ impl PartialEq for Profile {
 
 impl PartialOrd for Profile {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.name.partial_cmp(&other.name)
+        Some(self.cmp(other))
     }
 }
 
-impl ToString for Profile {
-    fn to_string(&self) -> String {
-        let mut result = Vec::new();
-        writeln!(&mut result, "[{}]", self.name).unwrap();
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "[{}]", self.name)?;
+        if self.is_abstract {
+            writeln!(f, "abstract = true")?;
+        }
+        if !self.extends.is_empty() {
+            writeln!(f, "extends = {}", string_list(&self.extends))?;
+        }
+        if !self.directories.is_empty() {
+            writeln!(f, "directories = {}", string_list(&self.directories))?;
+        }
+        if !self.remotes.is_empty() {
+            writeln!(f, "remotes = {}", string_list(&self.remotes))?;
+        }
         for (key, val) in self.fields.iter() {
-            writeln!(&mut result, "{} = {}", key, val.to_string()).unwrap();
+            writeln!(f, "{} = {}", toml_key(key), val)?;
+        }
+        if !self.env.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "[{}.env]", self.name)?;
+            for (name, val) in self.env.iter() {
+                match val {
+                    Some(v) => writeln!(f, "{} = {}", name, Value::String(v.to_string()))?,
+                    None => writeln!(f, "{} = false", name)?,
+                }
+            }
         }
-        from_utf8(&result).unwrap().to_string()
+        Ok(())
     }
 }
 
-#[derive(Debug)]
+/// Split a Git configuration key into its section, optional subsection and
+/// name. Subsections may themselves contain dots.
+pub fn split_key(key: &str) -> Vec<&str> {
+    match (key.split_once('.'), key.rsplit_once('.')) {
+        (Some((section, _)), Some((rest, name))) if rest.len() > section.len() => {
+            vec![section, &rest[section.len() + 1..], name]
+        }
+        (Some((section, name)), _) => vec![section, name],
+        _ => vec![key],
+    }
+}
+
+/// Find the key of `fields` that Git considers the same as `key`, ignoring
+/// the case of its section and name, and return it in its existing spelling.
+pub fn find_key<'a, V>(fields: &'a BTreeMap<String, V>, key: &str) -> Option<&'a str> {
+    let canonical = git::canonical_key(key);
+    fields
+        .keys()
+        .find(|k| git::canonical_key(k) == canonical)
+        .map(String::as_str)
+}
+
+/// Render a Git configuration key as a dotted TOML key, quoting segments that
+/// are not valid bare keys.
+pub fn toml_key(key: &str) -> String {
+    split_key(key)
+        .into_iter()
+        .map(|k| {
+            if !k.is_empty()
+                && k.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                k.to_string()
+            } else {
+                Value::String(k.to_string()).to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Render a list of strings as a TOML array.
+fn string_list(l: &[String]) -> String {
+    format!(
+        "[{}]",
+        l.iter()
+            .map(|s| Value::String(s.to_string()).to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Boolean(bool),
     ColorArray(Vec<Color>),
     Integer(i64),
+    /// Multi-valued key, such as `include.path` or `url.<base>.insteadOf`.
+    Multiple(Vec<Value>),
     String(String),
 }
 
+impl Value {
+    /// Individual values; a single value for anything but
+    /// [`Value::Multiple`].
+    pub fn values(&self) -> Vec<&Value> {
+        match self {
+            Self::Multiple(v) => v.iter().collect(),
+            _ => vec![self],
+        }
+    }
+}
+
 impl FromGitStr for Value {
-    fn from_git_str(s: &str) -> Result<Self, String> {
+    fn from_git_str(s: &str) -> Result<Self, Error> {
         if let Ok(b) = s.parse::<bool>() {
-            return Ok(Self::Boolean(b));
+            Ok(Self::Boolean(b))
         } else if let Ok(i) = s.parse::<i64>() {
-            return Ok(Self::Integer(i));
+            Ok(Self::Integer(i))
         } else {
-            return Ok(Self::String(s.to_string()));
+            Ok(Self::String(s.to_string()))
         }
     }
 }
@@ -299,33 +992,49 @@ impl ToGitString for Value {
                 .collect::<Vec<_>>()
                 .join(" "),
             Self::Integer(i) => i.to_string(),
+            Self::Multiple(v) => v
+                .iter()
+                .map(|v| v.to_git_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
             Self::String(s) => s.to_string(),
         }
     }
 }
 
-impl ToString for Value {
-    fn to_string(&self) -> String {
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Self::Boolean(b) => b.to_string(),
-            Self::ColorArray(v) => format!(
+            Self::Boolean(b) => write!(f, "{}", b),
+            Self::ColorArray(v) => write!(
+                f,
                 "[{}]",
                 v.iter()
                     .map(|c| c.to_string())
                     .collect::<Vec<_>>()
                     .join(", ")
             ),
-            Self::Integer(i) => i.to_string(),
+            Self::Integer(i) => write!(f, "{}", i),
+            Self::Multiple(v) => write!(
+                f,
+                "[{}]",
+                v.iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
             Self::String(s) => {
                 if s.contains("\n") {
-                    format!(
+                    write!(
+                        f,
                         "\"\"\"{}\"\"\"",
                         s.replace("\\", "\\\\")
                             .replace("\"", "\\\"")
                             .replace("\t", "\\t")
                     )
                 } else {
-                    format!(
+                    write!(
+                        f,
                         "\"{}\"",
                         s.replace("\\", "\\\\")
                             .replace("\"", "\\\"")
@@ -337,12 +1046,36 @@ impl ToString for Value {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Color {
     Number(u32),
     String(String),
 }
 
+impl FromGitStr for Color {
+    /// Parse a single color or attribute word of a Git color value.
+    fn from_git_str(s: &str) -> Result<Self, Error> {
+        if s.is_empty() || s.contains(char::is_whitespace) {
+            return Err(unsupported(
+                s,
+                "a color must be a single color name, attribute, number or hex code",
+            ));
+        }
+        if let Ok(i) = s.parse::<u8>() {
+            Ok(Self::Number(i as u32))
+        } else if let Some(hex) = s.strip_prefix('#')
+            && hex.len() == 6
+            && let Ok(i) = u32::from_str_radix(hex, 16)
+            // Numbers below 256 are 8-bit colors, keep such hex codes as is
+            && i >= 256
+        {
+            Ok(Self::Number(i))
+        } else {
+            Ok(Self::String(s.to_string()))
+        }
+    }
+}
+
 impl ToGitString for Color {
     fn to_git_string(&self) -> String {
         match self {
@@ -350,7 +1083,7 @@ impl ToGitString for Color {
                 if *i < 256 {
                     i.to_string()
                 } else {
-                    format!("#{:x}", i)
+                    format!("#{:06x}", i)
                 }
             }
             Self::String(s) => s.to_string(),
@@ -358,17 +1091,134 @@ impl ToGitString for Color {
     }
 }
 
-impl ToString for Color {
-    fn to_string(&self) -> String {
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::Number(i) => {
                 if *i < 256 {
-                    i.to_string()
+                    write!(f, "{}", i)
                 } else {
-                    format!("0x{:x}", i)
+                    write!(f, "0x{:x}", i)
                 }
             }
-            Self::String(s) => format!("\"{}\"", s),
+            Self::String(s) => write!(f, "\"{}\"", s),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_env_names() {
+        assert!(is_env_name("GIT_SSH_COMMAND"));
+        assert!(is_env_name("_x1"));
+        assert!(!is_env_name(""));
+        assert!(!is_env_name("1X"));
+        assert!(!is_env_name("X; touch ~/pwned #"));
+        assert!(!is_env_name("A-B"));
+    }
+
+    #[test]
+    fn rejects_invalid_env_names() {
+        let config = "[p.env]\n\"X; touch ~/pwned #\" = \"1\"\n".parse::<Config>();
+        assert!(matches!(config, Err(Error::UnsupportedValue { .. })));
+    }
+
+    #[test]
+    fn splits_keys() {
+        assert_eq!(split_key("user.name"), ["user", "name"]);
+        assert_eq!(split_key("color.diff.old"), ["color", "diff", "old"]);
+        assert_eq!(
+            split_key("url.git@github.com:.insteadOf"),
+            ["url", "git@github.com:", "insteadOf"]
+        );
+        assert_eq!(
+            split_key("includeIf.gitdir:~/work/.path"),
+            ["includeIf", "gitdir:~/work/", "path"]
+        );
+        assert_eq!(split_key("section..name"), ["section", "", "name"]);
+        assert_eq!(split_key("user"), ["user"]);
+    }
+
+    #[test]
+    fn renames_parents_in_place() {
+        let mut config =
+            "[a]\n\n[b]\n\n[c]\nextends = \"a\" # parent\n\n[d]\nextends = [\"b\", \"a\"]\n"
+                .parse::<Config>()
+                .unwrap();
+        config.rename_profile("a", "z").unwrap();
+        assert_eq!(
+            config.to_string(),
+            "[z]\n\n[b]\n\n[c]\nextends = \"z\" # parent\n\n[d]\nextends = [\"b\", \"z\"]\n"
+        );
+        assert_eq!(config.profile("d").unwrap().extends, ["b", "z"]);
+    }
+
+    #[test]
+    fn matches_field_keys_as_git() {
+        let mut config = "[p]\nuser.name = \"A\"\n".parse::<Config>().unwrap();
+        config
+            .set_field("p", "User.Name", Value::String("B".to_string()))
+            .unwrap();
+        assert_eq!(config.to_string(), "[p]\nuser.name = \"B\"\n");
+        assert!(config.remove_field("p", "USER.NAME").unwrap().is_some());
+        assert!(config.profile("p").unwrap().fields.is_empty());
+    }
+
+    #[test]
+    fn keeps_leading_comments_of_removed_settings() {
+        let source = "# my config\nactive = \"a\"\n\n[a]\n\n[b]\n";
+        let mut config = source.parse::<Config>().unwrap();
+        config.remove_profile("a");
+        assert_eq!(config.to_string(), "# my config\n\n[b]\n");
+
+        let mut config = source.parse::<Config>().unwrap();
+        config.set_active(None);
+        assert_eq!(config.to_string(), "# my config\n\n[a]\n\n[b]\n");
+    }
+
+    #[test]
+    fn rejects_inheritance_cycles() {
+        let config =
+            "[a]\nextends = \"b\"\n[b]\nextends = \"c\"\n[c]\nextends = \"a\"\n".parse::<Config>();
+        let Err(Error::Inheritance(message)) = config else {
+            panic!("cycle not detected");
+        };
+        assert_eq!(message, "profile inheritance cycle: a -> b -> c -> a");
+
+        let config = "[a]\nextends = \"a\"\n".parse::<Config>();
+        assert!(matches!(config, Err(Error::Inheritance(_))));
+    }
+
+    #[test]
+    fn rejects_unknown_parents() {
+        let config = "[a]\nextends = [\"b\"]\n".parse::<Config>();
+        let Err(Error::Inheritance(message)) = config else {
+            panic!("unknown parent not detected");
+        };
+        assert_eq!(message, "profile 'a' extends unknown profile 'b'");
+    }
+
+    #[test]
+    fn merges_parents_in_order() {
+        let config = "[a]\nuser.name = \"A\"\nuser.email = \"a@example.com\"\n\
+             [a.env]\nX = \"a\"\n\
+             [b]\nuser.name = \"B\"\ncore.pager = \"less\"\n\
+             [b.env]\nX = \"b\"\n\
+             [c]\nextends = [\"a\", \"b\"]\ncore.pager = \"cat\"\n"
+            .parse::<Config>()
+            .unwrap();
+        let resolved = config.resolve("c").unwrap();
+        let field = |key: &str| {
+            let (source, value) = resolved.fields[key];
+            (source, value.to_git_string())
+        };
+        // Later parents override earlier ones, and the profile overrides both
+        assert_eq!(field("user.name"), ("b", "B".to_string()));
+        assert_eq!(field("user.email"), ("a", "a@example.com".to_string()));
+        assert_eq!(field("core.pager"), ("c", "cat".to_string()));
+        assert_eq!(resolved.env["X"], ("b", &Some("b".to_string())));
+    }
+}
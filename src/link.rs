@@ -0,0 +1,221 @@
+//! Profiles linked into Git configuration with conditional includes.
+//!
+//! A linked profile is written as a standalone Git configuration file in the
+//! `profiles` directory next to the gid configuration file, and included from
+//! the global Git configuration with `includeIf` entries, so that Git applies
+//! it to matching repositories even when run without `gid`.
+
+use crate::{
+    Error, Profile,
+    git::{self, Scope},
+    glob, split_key,
+};
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Condition of an `includeIf` entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    /// Repository Git directory matches a pattern.
+    GitDir(String),
+    /// URL of any remote matches a pattern.
+    Remote(String),
+}
+
+impl Condition {
+    /// Prefix of remote URL conditions.
+    const REMOTE: &'static str = "hasconfig:remote.*.url:";
+
+    /// Parse the subsection of an `includeIf` entry.
+    pub fn parse(subsection: &str) -> Option<Self> {
+        if let Some(pattern) = subsection.strip_prefix("gitdir:") {
+            Some(Self::GitDir(pattern.to_string()))
+        } else {
+            subsection
+                .strip_prefix(Self::REMOTE)
+                .map(|pattern| Self::Remote(pattern.to_string()))
+        }
+    }
+
+    /// Configuration key of the included path.
+    pub fn key(&self) -> String {
+        format!("includeIf.{}.path", self)
+    }
+
+    /// Conditions matching the directory and remote rules of a profile.
+    pub fn from_rules(profile: &Profile) -> Vec<Self> {
+        Self::from_patterns(&profile.directories, &profile.remotes)
+    }
+
+    /// Conditions matching directory and remote patterns written as profile
+    /// rules. Directory rules match repository roots, so patterns that do not
+    /// match a whole directory tree are converted to match the `.git`
+    /// directory within. Remote patterns that are not URL patterns are
+    /// converted to patterns matching HTTPS, SSH and scp-like URLs below the
+    /// given host and path.
+    pub fn from_patterns(directories: &[String], remotes: &[String]) -> Vec<Self> {
+        let mut conditions: Vec<Self> = directories
+            .iter()
+            .map(|d| {
+                if d.ends_with('/') {
+                    Self::GitDir(d.to_string())
+                } else {
+                    Self::GitDir(format!("{}/.git", d))
+                }
+            })
+            .collect();
+        for remote in remotes.iter() {
+            let remote = remote.trim_end_matches('/');
+            if remote.contains("://") || remote.contains('@') {
+                conditions.push(Self::Remote(remote.to_string()));
+                continue;
+            }
+            conditions.push(Self::Remote(format!("*://{}/**", remote)));
+            conditions.push(Self::Remote(format!("*://*@{}/**", remote)));
+            match remote.split_once('/') {
+                Some((host, path)) => {
+                    conditions.push(Self::Remote(format!("*@{}:{}/**", host, path)))
+                }
+                None => conditions.push(Self::Remote(format!("*@{}:**", remote))),
+            }
+        }
+        conditions
+    }
+
+    /// Add the directory or remote rule matching the condition to a profile,
+    /// unless it already has it. URL patterns of the forms written by
+    /// [`Condition::from_rules`] are converted back to a host and path rule.
+    pub fn add_rule(&self, profile: &mut Profile) {
+        let (rules, rule) = match self {
+            Self::GitDir(pattern) => {
+                // Rules match the repository root rather than the Git directory
+                let pattern = pattern.strip_suffix("/.git").unwrap_or(pattern);
+                (&mut profile.directories, pattern.to_string())
+            }
+            Self::Remote(pattern) => {
+                let host_path = pattern
+                    .strip_prefix("*://*@")
+                    .or_else(|| pattern.strip_prefix("*://"))
+                    .and_then(|p| p.strip_suffix("/**"))
+                    .map(String::from)
+                    .or_else(|| {
+                        let (host, path) = pattern.strip_prefix("*@")?.split_once(':')?;
+                        match path.strip_suffix("/**") {
+                            Some(path) => Some(format!("{}/{}", host, path)),
+                            None if path == "**" => Some(host.to_string()),
+                            None => None,
+                        }
+                    })
+                    .filter(|p| !p.is_empty() && !p.contains(['@', ':']) && !glob::is_glob(p));
+                (
+                    &mut profile.remotes,
+                    host_path.unwrap_or(pattern.to_string()),
+                )
+            }
+        };
+        if !rules.contains(&rule) {
+            rules.push(rule);
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::GitDir(pattern) => write!(f, "gitdir:{}", pattern),
+            Self::Remote(pattern) => write!(f, "{}{}", Self::REMOTE, pattern),
+        }
+    }
+}
+
+/// `includeIf` entry of the global Git configuration including a linked
+/// profile.
+#[derive(Debug, Clone)]
+pub struct Link {
+    pub profile: String,
+    pub condition: Condition,
+    /// Included file, as written in the Git configuration.
+    pub path: String,
+}
+
+/// Directory holding the files of linked profiles, within `config_dir`, the
+/// directory of the gid configuration file.
+pub fn profiles_dir(config_dir: &Path) -> PathBuf {
+    let dir = config_dir.join("profiles");
+    std::path::absolute(&dir).unwrap_or(dir)
+}
+
+/// File of a linked profile.
+pub fn profile_path(config_dir: &Path, name: &str) -> PathBuf {
+    profiles_dir(config_dir).join(format!("{}.gitconfig", name))
+}
+
+/// Links in the global Git configuration to files in the profiles directory.
+pub fn links(config_dir: &Path) -> Result<Vec<Link>, Error> {
+    let dir = profiles_dir(config_dir);
+    let mut links = Vec::new();
+    for entry in git::list(&Scope::Global)? {
+        let [section, subsection, name] = split_key(&entry.key)[..] else {
+            continue;
+        };
+        if !section.eq_ignore_ascii_case("includeIf") || !name.eq_ignore_ascii_case("path") {
+            continue;
+        }
+        let (Some(condition), Some(path)) = (Condition::parse(subsection), entry.value) else {
+            continue;
+        };
+        let file = Path::new(&path);
+        if file.parent() != Some(dir.as_path()) || file.extension().is_none_or(|e| e != "gitconfig")
+        {
+            continue;
+        }
+        if let Some(profile) = file.file_stem() {
+            links.push(Link {
+                profile: profile.to_string_lossy().to_string(),
+                condition,
+                path,
+            });
+        }
+    }
+    Ok(links)
+}
+
+/// Write the fields of a resolved profile to its file, replacing the file
+/// as a whole. Returns the path of the file.
+pub fn write(config_dir: &Path, profile: &Profile) -> Result<PathBuf, Error> {
+    let path = profile_path(config_dir, &profile.name);
+    let temp = path.with_extension("gitconfig.tmp");
+    let io_error = |p: &Path| {
+        let p = p.to_path_buf();
+        move |source| Error::Io {
+            path: Some(p),
+            source,
+        }
+    };
+
+    let dir = profiles_dir(config_dir);
+    fs::create_dir_all(&dir).map_err(io_error(&dir))?;
+    // Start from an empty file, which also exists if the profile has no
+    // fields
+    fs::write(&temp, "").map_err(io_error(&temp))?;
+    let scope = Scope::File(temp.to_path_buf());
+    for (key, val) in profile.config_pairs() {
+        git::run(&scope.config_args(&["--add", &key, &val]))?;
+    }
+    fs::rename(&temp, &path).map_err(io_error(&path))?;
+    Ok(path)
+}
+
+/// Remove the file of a linked profile, if any.
+pub fn remove(config_dir: &Path, name: &str) -> Result<(), Error> {
+    let path = profile_path(config_dir, name);
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(Error::Io {
+            path: Some(path),
+            source: e,
+        }),
+        _ => Ok(()),
+    }
+}
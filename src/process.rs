@@ -0,0 +1,270 @@
+//! Child process execution shared by `gid` and `gidc`.
+//!
+//! Exit codes for gid's own failures follow the conventions of wrappers such
+//! as `env` and `timeout`, leaving other codes to the wrapped command.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    io::{self, ErrorKind},
+    process::Command,
+};
+
+/// gid itself failed, for example due to a missing or invalid configuration.
+pub const EXIT_FAILURE: i32 = 125;
+
+/// The command was found but could not be executed.
+pub const EXIT_CANNOT_EXECUTE: i32 = 126;
+
+/// The command could not be found.
+pub const EXIT_NOT_FOUND: i32 = 127;
+
+/// Run `command` in place of the current process, so that its exit status
+/// and any terminating signal become those of the current process. Only
+/// returns if the command could not be started.
+///
+/// On Unix the current process image is replaced with `exec`. Elsewhere the
+/// command is waited on and its exit code is passed on.
+pub fn exec(mut command: Command) -> io::Error {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.exec()
+    }
+
+    #[cfg(not(unix))]
+    {
+        match command.status() {
+            Ok(status) => std::process::exit(status.code().unwrap_or(EXIT_FAILURE)),
+            Err(e) => e,
+        }
+    }
+}
+
+/// Exit code to report when a command could not be started.
+pub fn exec_error_code(e: &io::Error) -> i32 {
+    match e.kind() {
+        ErrorKind::NotFound => EXIT_NOT_FOUND,
+        _ => EXIT_CANNOT_EXECUTE,
+    }
+}
+
+/// Git configuration as `-c key=value` arguments, to be passed before the Git
+/// subcommand.
+pub fn config_args(pairs: &[(String, String)]) -> Vec<String> {
+    let mut result = Vec::new();
+    for (key, val) in pairs.iter() {
+        result.push("-c".to_string());
+        result.push(format!("{}={}", key, val));
+    }
+    result
+}
+
+/// Number of leading `GIT_CONFIG_*` entries of the environment that hold the
+/// configuration of a profile rather than entries set by the user.
+pub const PROFILE_COUNT_VAR: &str = "GID_CONFIG_COUNT";
+
+/// Value of a variable of the current environment.
+fn current_var(name: &str) -> Option<String> {
+    env::var(name).ok()
+}
+
+/// `GIT_CONFIG_*` entries of an environment, and how many of the leading
+/// entries were set from a profile.
+fn env_entries(var: impl Fn(&str) -> Option<String>) -> (Vec<(String, String)>, usize) {
+    let count = |name: &str| {
+        var(name)
+            .and_then(|c| c.trim().parse::<usize>().ok())
+            .unwrap_or(0)
+    };
+    let entries: Vec<(String, String)> = (0..count("GIT_CONFIG_COUNT"))
+        .map(|i| {
+            (
+                var(&format!("GIT_CONFIG_KEY_{}", i)).unwrap_or_default(),
+                var(&format!("GIT_CONFIG_VALUE_{}", i)).unwrap_or_default(),
+            )
+        })
+        .collect();
+    let from_profile = count(PROFILE_COUNT_VAR).min(entries.len());
+    (entries, from_profile)
+}
+
+/// Git configuration as `GIT_CONFIG_COUNT`, `GIT_CONFIG_KEY_<n>` and
+/// `GIT_CONFIG_VALUE_<n>` environment variables.
+///
+/// Entries already present in the current environment are kept, renumbered
+/// after the given pairs, so that they still take precedence over the
+/// profile in the same way user `-c` arguments do. Entries of a profile
+/// applied earlier, as counted by [`PROFILE_COUNT_VAR`], are replaced.
+pub fn config_env(pairs: &[(String, String)]) -> Vec<(String, String)> {
+    config_env_in(pairs, current_var)
+}
+
+/// [`config_env`] for an environment given by `var`.
+fn config_env_in(
+    pairs: &[(String, String)],
+    var: impl Fn(&str) -> Option<String>,
+) -> Vec<(String, String)> {
+    let (existing, from_profile) = env_entries(var);
+    let mut entries: Vec<(String, String)> = pairs.to_vec();
+    entries.extend(existing.into_iter().skip(from_profile));
+
+    let mut result = vec![
+        ("GIT_CONFIG_COUNT".to_string(), entries.len().to_string()),
+        (PROFILE_COUNT_VAR.to_string(), pairs.len().to_string()),
+    ];
+    for (i, (key, val)) in entries.into_iter().enumerate() {
+        result.push((format!("GIT_CONFIG_KEY_{}", i), key));
+        result.push((format!("GIT_CONFIG_VALUE_{}", i), val));
+    }
+    result
+}
+
+/// Changes to the current environment applying [`config_env`], which also
+/// remove entries left unused. Variables set to `None` are removed. Without
+/// pairs, the entries of a profile applied earlier are reverted.
+pub fn config_env_changes(pairs: &[(String, String)]) -> Vec<(String, Option<String>)> {
+    config_env_changes_in(pairs, current_var)
+}
+
+/// [`config_env_changes`] for an environment given by `var`.
+fn config_env_changes_in(
+    pairs: &[(String, String)],
+    var: impl Fn(&str) -> Option<String> + Copy,
+) -> Vec<(String, Option<String>)> {
+    let (existing, from_profile) = env_entries(var);
+    let count = pairs.len() + existing.len() - from_profile;
+    let mut result: Vec<(String, Option<String>)> = config_env_in(pairs, var)
+        .into_iter()
+        .map(|(var, val)| match var.as_str() {
+            "GIT_CONFIG_COUNT" if count == 0 => (var, None),
+            PROFILE_COUNT_VAR if pairs.is_empty() => (var, None),
+            _ => (var, Some(val)),
+        })
+        .collect();
+    for i in count..existing.len() {
+        result.push((format!("GIT_CONFIG_KEY_{}", i), None));
+        result.push((format!("GIT_CONFIG_VALUE_{}", i), None));
+    }
+    result
+}
+
+/// Apply profile environment variables to a command, removing variables set
+/// to `None`.
+pub fn set_env(command: &mut Command, vars: &BTreeMap<String, Option<String>>) {
+    for (name, val) in vars.iter() {
+        match val {
+            Some(v) => command.env(name, v),
+            None => command.env_remove(name),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn environment(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> + Copy {
+        move |name| {
+            vars.iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.to_string())
+        }
+    }
+
+    #[test]
+    fn keeps_user_entries_after_profile() {
+        let env = environment(&[
+            ("GIT_CONFIG_COUNT", "1"),
+            ("GIT_CONFIG_KEY_0", "core.pager"),
+            ("GIT_CONFIG_VALUE_0", "less"),
+        ]);
+        assert_eq!(
+            config_env_in(&pairs(&[("user.name", "A")]), env),
+            pairs(&[
+                ("GIT_CONFIG_COUNT", "2"),
+                ("GID_CONFIG_COUNT", "1"),
+                ("GIT_CONFIG_KEY_0", "user.name"),
+                ("GIT_CONFIG_VALUE_0", "A"),
+                ("GIT_CONFIG_KEY_1", "core.pager"),
+                ("GIT_CONFIG_VALUE_1", "less"),
+            ])
+        );
+    }
+
+    #[test]
+    fn replaces_previous_profile() {
+        let env = environment(&[
+            ("GIT_CONFIG_COUNT", "3"),
+            ("GID_CONFIG_COUNT", "2"),
+            ("GIT_CONFIG_KEY_0", "user.name"),
+            ("GIT_CONFIG_VALUE_0", "A"),
+            ("GIT_CONFIG_KEY_1", "user.email"),
+            ("GIT_CONFIG_VALUE_1", "a@example.com"),
+            ("GIT_CONFIG_KEY_2", "core.pager"),
+            ("GIT_CONFIG_VALUE_2", "less"),
+        ]);
+        assert_eq!(
+            config_env_in(&pairs(&[("user.name", "B")]), env),
+            pairs(&[
+                ("GIT_CONFIG_COUNT", "2"),
+                ("GID_CONFIG_COUNT", "1"),
+                ("GIT_CONFIG_KEY_0", "user.name"),
+                ("GIT_CONFIG_VALUE_0", "B"),
+                ("GIT_CONFIG_KEY_1", "core.pager"),
+                ("GIT_CONFIG_VALUE_1", "less"),
+            ])
+        );
+    }
+
+    #[test]
+    fn unset_clears_leftover_entries() {
+        let env = environment(&[
+            ("GIT_CONFIG_COUNT", "3"),
+            ("GID_CONFIG_COUNT", "2"),
+            ("GIT_CONFIG_KEY_0", "user.name"),
+            ("GIT_CONFIG_VALUE_0", "A"),
+            ("GIT_CONFIG_KEY_1", "user.email"),
+            ("GIT_CONFIG_VALUE_1", "a@example.com"),
+            ("GIT_CONFIG_KEY_2", "core.pager"),
+            ("GIT_CONFIG_VALUE_2", "less"),
+        ]);
+        let set = |var: &str, val: &str| (var.to_string(), Some(val.to_string()));
+        let unset = |var: &str| (var.to_string(), None);
+        assert_eq!(
+            config_env_changes_in(&[], env),
+            [
+                set("GIT_CONFIG_COUNT", "1"),
+                unset("GID_CONFIG_COUNT"),
+                set("GIT_CONFIG_KEY_0", "core.pager"),
+                set("GIT_CONFIG_VALUE_0", "less"),
+                unset("GIT_CONFIG_KEY_1"),
+                unset("GIT_CONFIG_VALUE_1"),
+                unset("GIT_CONFIG_KEY_2"),
+                unset("GIT_CONFIG_VALUE_2"),
+            ]
+        );
+        let env = environment(&[
+            ("GIT_CONFIG_COUNT", "1"),
+            ("GID_CONFIG_COUNT", "1"),
+            ("GIT_CONFIG_KEY_0", "user.name"),
+            ("GIT_CONFIG_VALUE_0", "A"),
+        ]);
+        assert_eq!(
+            config_env_changes_in(&[], env),
+            [
+                unset("GIT_CONFIG_COUNT"),
+                unset("GID_CONFIG_COUNT"),
+                unset("GIT_CONFIG_KEY_0"),
+                unset("GIT_CONFIG_VALUE_0"),
+            ]
+        );
+    }
+}
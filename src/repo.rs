@@ -0,0 +1,320 @@
+use std::{
+    env,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+/// Git repository a command operates on.
+#[derive(Debug)]
+pub struct Repository {
+    /// Repository root; the work tree, or the Git directory for bare
+    /// repositories.
+    pub root: PathBuf,
+    /// Explicitly provided Git directory, if any.
+    pub git_dir: Option<PathBuf>,
+}
+
+impl Repository {
+    /// Locate the repository a Git command would operate on, taking into
+    /// account the `-C`, `--git-dir` and `--work-tree` global options at the
+    /// start of `args`, as well as the `GIT_DIR` and `GIT_WORK_TREE`
+    /// environment variables.
+    pub fn from_git_args<S: AsRef<OsStr>>(args: &[S]) -> Option<Self> {
+        let mut dir = env::current_dir().ok()?;
+        let mut git_dir: Option<PathBuf> = env::var_os("GIT_DIR").map(PathBuf::from);
+        let mut work_tree: Option<PathBuf> = env::var_os("GIT_WORK_TREE").map(PathBuf::from);
+
+        let mut args = args.iter().map(|a| a.as_ref());
+        while let Some(arg) = args.next() {
+            let arg = arg.to_string_lossy();
+            if !arg.starts_with('-') {
+                // First non-option argument is the Git subcommand
+                break;
+            }
+
+            if let Some(p) = arg.strip_prefix("--git-dir=") {
+                git_dir = Some(PathBuf::from(p));
+            } else if let Some(p) = arg.strip_prefix("--work-tree=") {
+                work_tree = Some(PathBuf::from(p));
+            } else {
+                match arg.as_ref() {
+                    "-C" => {
+                        let p = args.next()?;
+                        if !p.is_empty() {
+                            dir = dir.join(p);
+                        }
+                    }
+                    "--git-dir" => git_dir = Some(PathBuf::from(args.next()?)),
+                    "--work-tree" => work_tree = Some(PathBuf::from(args.next()?)),
+                    // Global options taking a separate value
+                    "-c" | "--config-env" | "--namespace" | "--super-prefix" | "--list-cmds"
+                    | "--attr-source" => {
+                        args.next()?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Relative paths are resolved against the directory after all `-C`
+        // options, as Git does
+        Self::discover(&dir, git_dir.as_deref(), work_tree.as_deref())
+    }
+
+    /// Locate the repository containing `dir`, with optional explicit Git
+    /// directory and work tree paths (relative to `dir`).
+    pub fn discover(dir: &Path, git_dir: Option<&Path>, work_tree: Option<&Path>) -> Option<Self> {
+        let git_dir = git_dir.map(|g| dir.join(g));
+
+        if let Some(w) = work_tree {
+            return Some(Repository {
+                root: dir.join(w),
+                git_dir,
+            });
+        }
+
+        if let Some(g) = git_dir {
+            let root = match g.file_name() {
+                Some(n) if n == ".git" => g.parent().map(Path::to_path_buf).unwrap_or(g.clone()),
+                _ => g.clone(),
+            };
+            return Some(Repository {
+                root,
+                git_dir: Some(g),
+            });
+        }
+
+        for ancestor in dir.ancestors() {
+            if ancestor.join(".git").exists() || is_bare(ancestor) {
+                return Some(Repository {
+                    root: ancestor.to_path_buf(),
+                    git_dir: None,
+                });
+            }
+        }
+        None
+    }
+
+    /// Remote names and URLs configured in the repository, with `origin`
+    /// first and the remaining remotes in name order.
+    pub fn remotes(&self) -> Vec<(String, String)> {
+        let mut command = crate::git::command();
+        match self.git_dir {
+            Some(ref g) => command.arg("--git-dir").arg(g),
+            None => command.arg("-C").arg(&self.root),
+        };
+        let output = command
+            .args(["config", "--null", "--get-regexp", r"^remote\..*\.url$"])
+            .output();
+
+        let mut result: Vec<(String, String)> = Vec::new();
+        if let Ok(o) = output {
+            for entry in String::from_utf8_lossy(&o.stdout).split('\0') {
+                let Some((key, url)) = entry.split_once('\n') else {
+                    continue;
+                };
+                if let Some(name) = key
+                    .strip_prefix("remote.")
+                    .and_then(|k| k.strip_suffix(".url"))
+                {
+                    result.push((name.to_string(), url.to_string()));
+                }
+            }
+        }
+        // Stable sort keeps multiple URLs of a remote in configured order
+        result.sort_by(|(a, _), (b, _)| (a != "origin", a).cmp(&(b != "origin", b)));
+        result
+    }
+
+    /// Path variants of the repository root used for rule matching, with
+    /// `/` separators. Includes the canonical path if it differs.
+    pub fn root_variants(&self) -> Vec<String> {
+        let mut result = vec![normalize(&self.root)];
+        if let Ok(p) = self.root.canonicalize() {
+            let p = normalize(&p);
+            if !result.contains(&p) {
+                result.push(p);
+            }
+        }
+        result
+    }
+}
+
+/// Check whether a directory looks like a bare Git repository.
+fn is_bare(dir: &Path) -> bool {
+    dir.join("HEAD").is_file() && dir.join("objects").is_dir() && dir.join("refs").is_dir()
+}
+
+/// Render a path with `/` separators and without a trailing separator.
+fn normalize(p: &Path) -> String {
+    let mut s = p.to_string_lossy().replace('\\', "/");
+    // Strip Windows verbatim prefix added by canonicalization
+    if let Some(stripped) = s.strip_prefix("//?/") {
+        s = stripped.to_string();
+    }
+    while s.len() > 1 && s.ends_with('/') {
+        s.pop();
+    }
+    s
+}
+
+/// Expand a directory rule pattern into a glob matched against repository
+/// roots. Follows the conventions of Git's `includeIf "gitdir:..."`: a
+/// leading `~/` expands to the home directory, patterns that are not absolute
+/// are prefixed with `**/`, and a trailing `/` matches everything beneath the
+/// directory.
+pub fn expand_directory_pattern(pattern: &str) -> String {
+    let pattern = pattern.replace('\\', "/");
+    let mut result = if let Some(rest) = pattern.strip_prefix("~/") {
+        match home_dir() {
+            Some(h) => format!("{}/{}", normalize(&h), rest),
+            None => pattern.to_string(),
+        }
+    } else if pattern.starts_with('/') || is_drive_absolute(&pattern) {
+        pattern.to_string()
+    } else {
+        format!("**/{}", pattern)
+    };
+
+    if result.ends_with('/') {
+        result.push_str("**");
+    }
+    result
+}
+
+/// Check whether a repository root matches a directory rule pattern.
+pub fn directory_matches(pattern: &str, root: &str) -> bool {
+    crate::glob::matches_path(pattern, root)
+        || crate::glob::matches_path(pattern, &format!("{}/", root))
+}
+
+fn is_drive_absolute(p: &str) -> bool {
+    let b = p.as_bytes();
+    b.len() >= 3 && b[0].is_ascii_alphabetic() && b[1] == b':' && b[2] == b'/'
+}
+
+/// Current user's home directory.
+pub fn home_dir() -> Option<PathBuf> {
+    let home_env = if env::consts::OS == "windows" {
+        "USERPROFILE"
+    } else {
+        "HOME"
+    };
+    env::var_os(home_env).map(PathBuf::from)
+}
+
+/// Normalize a remote URL to `host/path` form, without user, port, scheme or
+/// trailing `.git`. Local paths are returned with `/` separators.
+pub fn normalize_remote_url(url: &str) -> String {
+    let url = url.trim();
+    let (host, path) = if let Some((_, rest)) = url.split_once("://") {
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let host = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+        let host = host.split_once(':').map_or(host, |(h, _)| h);
+        (host.to_lowercase(), path.to_string())
+    } else if let Some((authority, path)) = url.split_once(':')
+        && !authority.contains('/')
+        && authority.len() > 1
+    {
+        // scp-like syntax, `[user@]host:path`
+        let host = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+        (host.to_lowercase(), path.to_string())
+    } else {
+        return url.replace('\\', "/");
+    };
+
+    let path = path.trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    if path.is_empty() {
+        host
+    } else {
+        format!("{}/{}", host, path)
+    }
+}
+
+/// Check whether a remote URL matches a remote rule pattern.
+///
+/// Patterns containing a scheme (`://`) or user (`@`) are matched against the
+/// raw URL. Other patterns are matched against the normalized `host/path`
+/// form, where a host or leading path (`github.com/org`) matches all
+/// repositories beneath it.
+pub fn remote_matches(pattern: &str, url: &str) -> bool {
+    if pattern.contains("://") || pattern.contains('@') {
+        return crate::glob::matches_path(pattern, url);
+    }
+
+    let pattern = pattern.trim_matches('/');
+    let pattern = pattern.strip_suffix(".git").unwrap_or(pattern);
+    let url = normalize_remote_url(url);
+    crate::glob::matches_path(pattern, &url)
+        || crate::glob::matches_path(&format!("{}/**", pattern), &url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_remote_urls() {
+        let cases = [
+            ("https://github.com/acme/web.git", "github.com/acme/web"),
+            (
+                "https://user@GitHub.com:8443/acme/web/",
+                "github.com/acme/web",
+            ),
+            (
+                "ssh://git@gerrit.example:29418/acme/web",
+                "gerrit.example/acme/web",
+            ),
+            ("git@github.com:acme/web.git", "github.com/acme/web"),
+            ("github.com:acme/web", "github.com/acme/web"),
+            ("https://gitlab.example", "gitlab.example"),
+            ("/srv/git/web.git", "/srv/git/web.git"),
+            (r"C:\repos\web", "C:/repos/web"),
+        ];
+        for (url, normalized) in cases {
+            assert_eq!(normalize_remote_url(url), normalized, "{}", url);
+        }
+    }
+
+    #[test]
+    fn matches_host_and_path_rules() {
+        for url in [
+            "https://github.com/acme/web.git",
+            "https://me@github.com:443/acme/web",
+            "ssh://git@github.com:22/acme/web.git",
+            "git@github.com:acme/web.git",
+        ] {
+            assert!(remote_matches("github.com/acme", url), "{}", url);
+            assert!(remote_matches("github.com", url), "{}", url);
+            assert!(remote_matches("github.com/acme/web.git", url), "{}", url);
+            assert!(!remote_matches("github.com/other", url), "{}", url);
+        }
+        assert!(!remote_matches(
+            "github.com/acme",
+            "https://github.com/acme-corp/web"
+        ));
+        assert!(remote_matches(
+            "*.acme.example",
+            "git@gitlab.acme.example:web.git"
+        ));
+    }
+
+    #[test]
+    fn matches_full_url_rules() {
+        let rule = "ssh://*@gerrit.example:29418/**";
+        assert!(remote_matches(
+            rule,
+            "ssh://git@gerrit.example:29418/acme/web"
+        ));
+        assert!(!remote_matches(rule, "https://gerrit.example/acme/web"));
+        assert!(remote_matches(
+            "git@github.com:acme/*",
+            "git@github.com:acme/web.git"
+        ));
+        assert!(!remote_matches(
+            "git@github.com:acme/*",
+            "https://github.com/acme/web"
+        ));
+    }
+}
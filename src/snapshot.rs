@@ -0,0 +1,158 @@
+//! Snapshots of Git configuration values replaced by profile exports.
+//!
+//! Each export that changes Git configuration pushes the prior values of the
+//! changed keys onto a stack, so that the export can later be undone. Local
+//! and worktree snapshots are kept in the Git directory of the worktree, and
+//! others next to the gid configuration file.
+
+use crate::{
+    Error,
+    git::{self, Scope},
+};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use toml_edit::{Array, ArrayOfTables, DocumentMut, Item, Table};
+
+/// Name of the file holding the snapshot stack.
+const FILE_NAME: &str = "gid-snapshots.toml";
+
+/// Prior values of the keys changed by an export.
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    /// Name of the exported profile.
+    pub profile: String,
+    /// Configuration the profile was exported to, from [`target`].
+    pub target: String,
+    /// Values of each changed key before the export, in Git string form. An
+    /// empty list means the key was not set.
+    pub values: BTreeMap<String, Vec<String>>,
+}
+
+/// Path of the snapshot stack of a scope. Snapshots of scopes outside of a
+/// repository are kept in `config_dir`, the directory of the gid
+/// configuration file.
+pub fn path(scope: &Scope, config_dir: &Path) -> Result<PathBuf, Error> {
+    match scope {
+        Scope::Local | Scope::Worktree => {
+            let output = git::output(&["rev-parse", "--absolute-git-dir"])?;
+            let git_dir = String::from_utf8_lossy(&output);
+            Ok(PathBuf::from(git_dir.trim_end()).join(FILE_NAME))
+        }
+        Scope::Global | Scope::System | Scope::File(_) => Ok(config_dir.join(FILE_NAME)),
+    }
+}
+
+/// Identify the configuration of a scope within its snapshot stack.
+pub fn target(scope: &Scope) -> String {
+    match scope {
+        Scope::Local => "local".to_string(),
+        Scope::Global => "global".to_string(),
+        Scope::System => "system".to_string(),
+        Scope::Worktree => "worktree".to_string(),
+        Scope::File(path) => {
+            let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+            format!("file:{}", path.display())
+        }
+    }
+}
+
+/// Read the snapshot stack at `path`, oldest first. A missing file is an
+/// empty stack.
+pub fn read(path: &Path) -> Result<Vec<Snapshot>, Error> {
+    let source = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(Error::Io {
+                path: Some(path.to_path_buf()),
+                source: e,
+            });
+        }
+    };
+    let table = source
+        .parse::<toml::Table>()
+        .map_err(|e| Error::from_toml(e, &source))?;
+    let invalid = |message: &str| Error::UnsupportedValue {
+        key: path.display().to_string(),
+        message: message.to_string(),
+    };
+
+    let mut snapshots = Vec::new();
+    let Some(entries) = table.get("snapshot") else {
+        return Ok(snapshots);
+    };
+    for entry in entries
+        .as_array()
+        .ok_or_else(|| invalid("snapshots must be an array of tables"))?
+    {
+        let mut snapshot = Snapshot {
+            profile: entry
+                .get("profile")
+                .and_then(toml::Value::as_str)
+                .ok_or_else(|| invalid("snapshot has no profile"))?
+                .to_string(),
+            target: entry
+                .get("target")
+                .and_then(toml::Value::as_str)
+                .ok_or_else(|| invalid("snapshot has no target"))?
+                .to_string(),
+            ..Default::default()
+        };
+        if let Some(values) = entry.get("values").and_then(toml::Value::as_table) {
+            for (key, list) in values.iter() {
+                let list = list
+                    .as_array()
+                    .ok_or_else(|| invalid("snapshot values must be arrays"))?
+                    .iter()
+                    .map(|v| v.as_str().map(String::from))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| invalid("snapshot values must be strings"))?;
+                snapshot.values.insert(key.to_string(), list);
+            }
+        }
+        snapshots.push(snapshot);
+    }
+    Ok(snapshots)
+}
+
+/// Write the snapshot stack at `path`, removing the file if the stack is
+/// empty.
+pub fn write(path: &Path, snapshots: &[Snapshot]) -> Result<(), Error> {
+    let io_error = |source| Error::Io {
+        path: Some(path.to_path_buf()),
+        source,
+    };
+    if snapshots.is_empty() {
+        return match fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(io_error(e)),
+            _ => Ok(()),
+        };
+    }
+
+    let mut entries = ArrayOfTables::new();
+    for snapshot in snapshots.iter() {
+        let mut entry = Table::new();
+        entry.insert("profile", toml_edit::value(snapshot.profile.as_str()));
+        entry.insert("target", toml_edit::value(snapshot.target.as_str()));
+        let mut values = Table::new();
+        for (key, list) in snapshot.values.iter() {
+            let list = list.iter().map(String::as_str).collect::<Array>();
+            values.insert(key, toml_edit::value(list));
+        }
+        entry.insert("values", Item::Table(values));
+        entries.push(entry);
+    }
+    let mut doc = DocumentMut::new();
+    doc.insert("snapshot", Item::ArrayOfTables(entries));
+    fs::write(path, doc.to_string()).map_err(io_error)
+}
+
+/// Push a snapshot onto the stack at `path`.
+pub fn push(path: &Path, snapshot: Snapshot) -> Result<(), Error> {
+    let mut snapshots = read(path)?;
+    snapshots.push(snapshot);
+    write(path, &snapshots)
+}